@@ -0,0 +1,213 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use virtual_machine16_bit::virtual_machine::{
+    cpu::CPU,
+    device::Device,
+    error::VmError,
+    instructions,
+    keyboard_device::{KeyboardDevice, ScriptedInput},
+    memory::Memory,
+    memory_mapper::MemoryMapper,
+    registers::Register,
+    rng_device::RngDevice,
+    rom_device::{RomDevice, WritePolicy},
+    timer_device::TimerDevice,
+};
+
+/// Maps 64 KiB of memory, either through the fast path or as a boxed device.
+fn mapper(fast_path: bool) -> MemoryMapper {
+    let mut memory_mapper = MemoryMapper::new();
+    if fast_path {
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+    } else {
+        memory_mapper
+            .map(Box::new(Memory::new(0x10000)), 0x0000, 0xFFFF, false)
+            .unwrap();
+    }
+    memory_mapper
+}
+
+/// Maps memory in small regions that are not aligned on pages, so that every
+/// access falls back to a scan of the regions sharing the page.
+fn mixed_page_mapper() -> MemoryMapper {
+    let mut memory_mapper = MemoryMapper::new();
+    for start in (0x0000..0x10000 - 0x80).step_by(0x100) {
+        memory_mapper
+            .map(
+                Box::new(Memory::new(0x100)),
+                start + 0x80,
+                start + 0x17F,
+                true,
+            )
+            .unwrap();
+    }
+    memory_mapper
+        .map(Box::new(Memory::new(0x80)), 0x0000, 0x007F, true)
+        .unwrap();
+    memory_mapper
+}
+
+/// A device with its start address, end address and whether addresses are remapped.
+type MappedRegion = (Box<dyn Device>, usize, usize, bool);
+
+/// The regions of a typical machine: RAM, a screen, a few devices and a boot ROM.
+/// Later regions take precedence, so the RAM is the last one scanned.
+fn typical_regions() -> Vec<MappedRegion> {
+    vec![
+        (Box::new(Memory::new(0x10000)), 0x0000, 0xFFFF, false),
+        (Box::new(Memory::new(0x100)), 0x3000, 0x30FF, true),
+        (Box::new(TimerDevice::new()), 0x4000, 0x4007, true),
+        (Box::new(RngDevice::new(0)), 0x4010, 0x4013, true),
+        (
+            Box::new(KeyboardDevice::new(ScriptedInput::new(&[]))),
+            0x4020,
+            0x4021,
+            true,
+        ),
+        (
+            Box::new(RomDevice::new(&[0; 0x1000], WritePolicy::Ignore)),
+            0xF000,
+            0xFFFF,
+            true,
+        ),
+    ]
+}
+
+fn typical_mapper() -> MemoryMapper {
+    let mut memory_mapper = MemoryMapper::new();
+    for (device, start, end, remap) in typical_regions() {
+        memory_mapper.map(device, start, end, remap).unwrap();
+    }
+    memory_mapper
+}
+
+/// Dispatches accesses like `MemoryMapper` did before it had a page table,
+/// by scanning the regions from the most recently mapped one.
+struct LinearScanMapper {
+    regions: Vec<MappedRegion>,
+}
+
+impl LinearScanMapper {
+    fn new() -> LinearScanMapper {
+        let mut regions = typical_regions();
+        regions.reverse();
+        LinearScanMapper { regions }
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        let (device, start, _, remap) = self
+            .regions
+            .iter_mut()
+            .find(|(_, start, end, _)| address >= *start && address <= *end)
+            .ok_or(VmError::Unmapped { address })?;
+
+        let address = if *remap { address - *start } else { address };
+        device.get_u8(address)
+    }
+}
+
+fn read_bytes(memory_mapper: &mut MemoryMapper) {
+    for address in 0x0000..0x1000 {
+        black_box(memory_mapper.get_u8(address).unwrap());
+    }
+}
+
+fn get_u8_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_u8");
+
+    let mut memory_mapper = mapper(true);
+    group.bench_function("memory_fast_path", |b| {
+        b.iter(|| read_bytes(&mut memory_mapper))
+    });
+
+    let mut memory_mapper = mapper(false);
+    group.bench_function("boxed_device", |b| {
+        b.iter(|| read_bytes(&mut memory_mapper))
+    });
+
+    let mut memory_mapper = mixed_page_mapper();
+    group.bench_function("mixed_pages", |b| b.iter(|| read_bytes(&mut memory_mapper)));
+
+    let mut memory_mapper = typical_mapper();
+    group.bench_function("typical_map_page_table", |b| {
+        b.iter(|| read_bytes(&mut memory_mapper))
+    });
+
+    let mut linear_scan_mapper = LinearScanMapper::new();
+    group.bench_function("typical_map_linear_scan", |b| {
+        b.iter(|| {
+            for address in 0x0000..0x1000 {
+                black_box(linear_scan_mapper.get_u8(address).unwrap());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn cpu_benchmark(c: &mut Criterion) {
+    #[rustfmt::skip]
+    let program = [
+        instructions::INC_REG, 1,                          // inc acc
+        instructions::JMP_NOT_EQ, 0x10, 0x00, 0x00, 0x00,  // jne $1000, &0000
+        instructions::HLT,
+    ];
+
+    c.bench_function("cpu_count_loop", |b| {
+        b.iter(|| {
+            let mut memory_mapper = mapper(true);
+            memory_mapper.load_image(&program, 0, "program").unwrap();
+            let mut cpu = CPU::new(memory_mapper).unwrap();
+            cpu.run().unwrap();
+            black_box(cpu.registers().get(Register::Acc));
+        })
+    });
+}
+
+/// Selects the execution engine of a CPU.
+type Engine = fn(&mut CPU);
+
+fn execution_benchmark(c: &mut Criterion) {
+    #[rustfmt::skip]
+    let program = [
+        instructions::MOV_LIT_REG, 0x00, 0x03, 3,          // mov $0003, r2
+        instructions::MUL_REG_REG, 3, 3,                   // mul r2, r2
+        instructions::PSH_REG, 1,                          // psh acc
+        instructions::POP, 4,                              // pop r3
+        instructions::MOV_REG_MEM, 4, 0x30, 0x00,          // mov r3, &3000
+        instructions::INC_REG, 2,                          // inc r1
+        instructions::MOV_REG_REG, 2, 1,                   // mov r1, acc
+        instructions::JMP_NOT_EQ, 0x10, 0x00, 0x00, 0x00,  // jne $1000, &0000
+        instructions::HLT,
+    ];
+
+    let engines: [(&str, Engine); 3] = [
+        ("interpreter", |_| {}),
+        ("instruction_cache", |cpu| cpu.set_instruction_cache(true)),
+        ("block_translation", |cpu| cpu.set_block_translation(true)),
+    ];
+
+    let mut group = c.benchmark_group("execution");
+    for (name, engine) in engines.iter() {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let mut memory_mapper = mapper(true);
+                memory_mapper.load_image(&program, 0, "program").unwrap();
+                let mut cpu = CPU::new(memory_mapper).unwrap();
+                engine(&mut cpu);
+                cpu.run().unwrap();
+                black_box(cpu.registers().get(Register::R3));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    get_u8_benchmark,
+    cpu_benchmark,
+    execution_benchmark
+);
+criterion_main!(benches);
@@ -0,0 +1,165 @@
+use crate::assembler::parser::ast::{
+    Expr, ExprKind, Instruction, InstructionKind, Register, Statement, StatementKind,
+};
+
+/// Offset from the frame pointer of the trampoline to the bank pushed by a far call.
+/// The CPU state pushed by `cal` takes the 0x18 bytes below, after the argument count.
+const FAR_CALL_BANK_OFFSET: u16 = 0x18;
+/// Offset from the frame pointer of the trampoline to the address pushed by a far call.
+const FAR_CALL_ADDRESS_OFFSET: u16 = 0x1A;
+
+/// The instructions of a program going to the same bank.
+#[derive(Debug, PartialEq)]
+pub struct BankSection {
+    /// The bank given by the `.bank` directive, or None for the code before any directive.
+    pub bank: Option<Expr>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// Groups the statements of a program by bank and expands the far calls.
+/// The far calls go through the trampoline at the given address.
+pub fn split_banks(statements: Vec<Statement>, trampoline_address: u16) -> Vec<BankSection> {
+    let mut sections = vec![BankSection {
+        bank: None,
+        instructions: Vec::new(),
+    }];
+
+    for statement in statements {
+        match statement.kind {
+            StatementKind::Bank(bank) => sections.push(BankSection {
+                bank: Some(bank),
+                instructions: Vec::new(),
+            }),
+            StatementKind::FarCall(bank, address) => {
+                let section = sections.last_mut().unwrap();
+                section
+                    .instructions
+                    .extend(far_call(bank, address, trampoline_address));
+            }
+            StatementKind::Instruction(instruction) => {
+                sections.last_mut().unwrap().instructions.push(instruction)
+            }
+        }
+    }
+
+    // Drops the unbanked section if the program starts with a directive
+    if sections.len() > 1 && sections[0].instructions.is_empty() {
+        sections.remove(0);
+    }
+
+    sections
+}
+
+/// Returns the instructions calling the given address in the given bank through the trampoline.
+pub fn far_call(bank: Expr, address: Expr, trampoline_address: u16) -> Vec<Instruction> {
+    let address = match address.kind {
+        ExprKind::Address(address) => literal(address),
+        _ => address,
+    };
+
+    vec![
+        instruction(InstructionKind::PshLit(address)),
+        instruction(InstructionKind::PshLit(bank)),
+        instruction(InstructionKind::PshLit(literal(2))),
+        instruction(InstructionKind::CalLit(literal(trampoline_address))),
+    ]
+}
+
+/// Returns the routine performing the far calls: it selects the bank pushed by
+/// the caller, calls the pushed address and restores the bank of the caller.
+///
+/// The routine must be placed outside of the banked window, since the bank
+/// changes under it. The callee gets the registers r1 to r8 of the caller, but
+/// not `acc`, which the trampoline uses. The value of `acc` returned by the
+/// callee is preserved.
+pub fn far_call_trampoline(bank_select_address: u16) -> Vec<Instruction> {
+    vec![
+        // The bank of the caller waits on the stack so that no argument register is used
+        instruction(InstructionKind::MovMemReg(
+            address(bank_select_address),
+            Register::Acc,
+        )),
+        instruction(InstructionKind::PshReg(Register::Acc)),
+        instruction(InstructionKind::MovLitOffsetReg(
+            literal(FAR_CALL_BANK_OFFSET),
+            Register::Fp,
+            Register::Acc,
+        )),
+        instruction(InstructionKind::MovRegMem(
+            Register::Acc,
+            address(bank_select_address),
+        )),
+        instruction(InstructionKind::MovLitOffsetReg(
+            literal(FAR_CALL_ADDRESS_OFFSET),
+            Register::Fp,
+            Register::Acc,
+        )),
+        instruction(InstructionKind::PshLit(literal(0))),
+        instruction(InstructionKind::CalReg(Register::Acc)),
+        // The return of the trampoline restores r1 for the caller
+        instruction(InstructionKind::PopReg(Register::R1)),
+        instruction(InstructionKind::MovRegMem(
+            Register::R1,
+            address(bank_select_address),
+        )),
+        instruction(InstructionKind::Ret),
+    ]
+}
+
+fn instruction(kind: InstructionKind) -> Instruction {
+    Instruction { kind }
+}
+
+fn literal(value: u16) -> Expr {
+    Expr {
+        kind: ExprKind::HexLiteral(value),
+    }
+}
+
+fn address(value: u16) -> Expr {
+    Expr {
+        kind: ExprKind::Address(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser;
+
+    #[test]
+    fn split_banks_test() {
+        let statements =
+            parser::parse_program("mov $1, &9000\nfcl $2, &8000\nhlt\n.bank $2\nret").unwrap();
+        let sections = split_banks(statements, 0x0100);
+
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].bank, None);
+        assert_eq!(
+            sections[0].instructions[1..5],
+            far_call(literal(2), literal(0x8000), 0x0100)[..]
+        );
+        assert_eq!(sections[1].bank, Some(literal(2)));
+        assert_eq!(
+            sections[1].instructions,
+            vec![instruction(InstructionKind::Ret)]
+        );
+
+        let sections = split_banks(parser::parse_program(".bank $1\nhlt").unwrap(), 0x0100);
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].bank, Some(literal(1)));
+    }
+
+    #[test]
+    fn far_call_trampoline_test() {
+        let trampoline = far_call_trampoline(0x9000);
+
+        assert_eq!(trampoline.len(), 10);
+        assert_eq!(trampoline[6], parser::parse_instruction("cal acc").unwrap());
+        assert_eq!(trampoline[7], parser::parse_instruction("pop r1").unwrap());
+        assert_eq!(
+            trampoline[8],
+            parser::parse_instruction("mov r1, &9000").unwrap()
+        );
+    }
+}
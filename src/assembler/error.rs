@@ -0,0 +1,77 @@
+use nom::error::ErrorKind;
+use std::{error::Error, fmt};
+
+/// A location in the assembler source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    /// Byte offset of the start of the span.
+    pub start: usize,
+    /// Byte offset just past the end of the span.
+    pub end: usize,
+    /// Line number of the start of the span, starting at 1.
+    pub line: usize,
+    /// Column number of the start of the span, starting at 1.
+    pub column: usize,
+}
+
+impl Span {
+    /// Creates the span of the token at the start of `remaining`, which must be a suffix of `source`.
+    pub fn at(source: &str, remaining: &str) -> Span {
+        let start = source.len() - remaining.len();
+        let token_length = remaining
+            .find(char::is_whitespace)
+            .unwrap_or(remaining.len());
+        let preceding = &source[..start];
+        let line = preceding.matches('\n').count() + 1;
+        let column = preceding.len() - preceding.rfind('\n').map_or(0, |index| index + 1) + 1;
+
+        Span {
+            start,
+            end: start + token_length,
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Errors raised while assembling source code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AsmError {
+    /// The source does not match the expected syntax.
+    Syntax { span: Span, kind: ErrorKind },
+    /// The source ended in the middle of a statement.
+    UnexpectedEnd { span: Span },
+    /// A statement was parsed but some input was left over.
+    TrailingInput { span: Span },
+}
+
+impl AsmError {
+    /// Returns the location of the error in the source.
+    pub fn span(&self) -> Span {
+        match self {
+            AsmError::Syntax { span, .. } => *span,
+            AsmError::UnexpectedEnd { span } => *span,
+            AsmError::TrailingInput { span } => *span,
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::Syntax { span, kind } => {
+                write!(f, "{}: Syntax error ({})", span, kind.description())
+            }
+            AsmError::UnexpectedEnd { span } => write!(f, "{}: Unexpected end of input", span),
+            AsmError::TrailingInput { span } => write!(f, "{}: Unexpected input", span),
+        }
+    }
+}
+
+impl Error for AsmError {}
@@ -1 +1,3 @@
-pub mod parser;
\ No newline at end of file
+pub mod banks;
+pub mod error;
+pub mod parser;
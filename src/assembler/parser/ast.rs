@@ -1,3 +1,5 @@
+pub use crate::virtual_machine::registers::Register;
+
 #[derive(Debug, PartialEq)]
 pub struct Expr {
     pub kind: ExprKind,
@@ -29,6 +31,7 @@ pub enum InstructionKind {
     DecReg(Register),
     Hlt,
     IncReg(Register),
+    IntLit(Expr),
     JeqLitMem(Expr, Expr),
     JeqRegMem(Register, Expr),
     JgeLitMem(Expr, Expr),
@@ -41,6 +44,7 @@ pub enum InstructionKind {
     JltRegMem(Register, Expr),
     JneLitMem(Expr, Expr),
     JneRegMem(Register, Expr),
+    LptReg(Register),
     LsfRegLit(Register, Expr),
     LsfRegReg(Register, Register),
     MovLitMem(Expr, Expr),
@@ -59,34 +63,35 @@ pub enum InstructionKind {
     PshLit(Expr),
     PshReg(Register),
     Ret,
+    RetInt,
     RsfRegLit(Register, Expr),
     RsfRegReg(Register, Register),
     SubLitReg(Expr, Register),
     SubRegLit(Register, Expr),
     SubRegReg(Register, Register),
+    SysLit(Expr),
+    Usr,
     XorLitReg(Expr, Register),
     XorRegReg(Register, Register),
 }
 
+#[derive(Debug, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StatementKind {
+    /// `.bank` directive: the following statements go to the given bank.
+    Bank(Expr),
+    /// Call of an address in another bank: `fcl $bank, &address`.
+    FarCall(Expr, Expr),
+    Instruction(Instruction),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operator {
     OpPlus,
     OpMinus,
     OpMultiply,
 }
-
-#[derive(Clone, Debug, PartialEq)]
-pub enum Register {
-    R1,
-    R2,
-    R3,
-    R4,
-    R5,
-    R6,
-    R7,
-    R8,
-    Sp,
-    Fp,
-    Ip,
-    Acc,
-}
@@ -59,16 +59,13 @@ pub fn binary(input: &str) -> IResult<&str, ast::Expr> {
     fn term(input: &str) -> IResult<&str, ast::Expr> {
         let (mut input, mut node) = factor(input)?;
 
-        loop {
-            match opt(tuple((mult_operator, factor)))(input)? {
-                (remaining_input, Some((operator, right))) => {
-                    input = remaining_input;
-                    node = ast::Expr {
-                        kind: ast::ExprKind::Binary(Box::new(node), operator, Box::new(right)),
-                    };
-                }
-                _ => break,
-            }
+        while let (remaining_input, Some((operator, right))) =
+            opt(tuple((mult_operator, factor)))(input)?
+        {
+            input = remaining_input;
+            node = ast::Expr {
+                kind: ast::ExprKind::Binary(Box::new(node), operator, Box::new(right)),
+            };
         }
 
         Ok((input, node))
@@ -77,16 +74,13 @@ pub fn binary(input: &str) -> IResult<&str, ast::Expr> {
     fn expr(input: &str) -> IResult<&str, ast::Expr> {
         let (mut input, mut node) = term(input)?;
 
-        loop {
-            match opt(tuple((plus_minus_operator, term)))(input)? {
-                (remaining_input, Some((operator, right))) => {
-                    input = remaining_input;
-                    node = ast::Expr {
-                        kind: ast::ExprKind::Binary(Box::new(node), operator, Box::new(right)),
-                    };
-                }
-                _ => break,
-            }
+        while let (remaining_input, Some((operator, right))) =
+            opt(tuple((plus_minus_operator, term)))(input)?
+        {
+            input = remaining_input;
+            node = ast::Expr {
+                kind: ast::ExprKind::Binary(Box::new(node), operator, Box::new(right)),
+            };
         }
 
         Ok((input, node))
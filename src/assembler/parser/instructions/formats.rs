@@ -22,7 +22,7 @@ where
                 expressions::literal_expr,
                 space0,
             ),
-            |literal_expr| mapper(literal_expr),
+            &mapper,
         )(input)
     }
 }
@@ -132,7 +132,7 @@ where
                 types::register,
                 space0,
             ),
-            |register| mapper(register),
+            &mapper,
         )(input)
     }
 }
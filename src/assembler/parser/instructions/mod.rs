@@ -11,6 +11,7 @@ pub fn instruction(input: &str) -> IResult<&str, ast::Instruction> {
         dec,
         hlt,
         inc,
+        int,
         jeq,
         jge,
         jgt,
@@ -24,8 +25,7 @@ pub fn instruction(input: &str) -> IResult<&str, ast::Instruction> {
         or,
         pop,
         psh,
-        ret,
-        alt((rsf, sub, xor)), // alt only accepts 21 elements maximum
+        alt((lpt, ret, rsf, rti, sub, sys, usr, xor)), // alt only accepts 21 elements maximum
     ))(input)
 }
 
@@ -88,6 +88,12 @@ fn inc(input: &str) -> IResult<&str, ast::Instruction> {
     })(input)
 }
 
+fn int(input: &str) -> IResult<&str, ast::Instruction> {
+    formats::lit(String::from("int"), |literal_expr| ast::Instruction {
+        kind: ast::InstructionKind::IntLit(literal_expr),
+    })(input)
+}
+
 fn jeq(input: &str) -> IResult<&str, ast::Instruction> {
     alt((
         formats::reg_mem(String::from("jeq"), |register, address_expr| {
@@ -286,6 +292,12 @@ fn psh(input: &str) -> IResult<&str, ast::Instruction> {
     ))(input)
 }
 
+fn lpt(input: &str) -> IResult<&str, ast::Instruction> {
+    formats::reg(String::from("lpt"), |register| ast::Instruction {
+        kind: ast::InstructionKind::LptReg(register),
+    })(input)
+}
+
 fn ret(input: &str) -> IResult<&str, ast::Instruction> {
     formats::no_arg(String::from("ret"), || ast::Instruction {
         kind: ast::InstructionKind::Ret,
@@ -307,6 +319,12 @@ fn rsf(input: &str) -> IResult<&str, ast::Instruction> {
     ))(input)
 }
 
+fn rti(input: &str) -> IResult<&str, ast::Instruction> {
+    formats::no_arg(String::from("rti"), || ast::Instruction {
+        kind: ast::InstructionKind::RetInt,
+    })(input)
+}
+
 fn sub(input: &str) -> IResult<&str, ast::Instruction> {
     alt((
         formats::lit_reg(String::from("sub"), |literal_expr, register| {
@@ -327,6 +345,18 @@ fn sub(input: &str) -> IResult<&str, ast::Instruction> {
     ))(input)
 }
 
+fn sys(input: &str) -> IResult<&str, ast::Instruction> {
+    formats::lit(String::from("sys"), |literal_expr| ast::Instruction {
+        kind: ast::InstructionKind::SysLit(literal_expr),
+    })(input)
+}
+
+fn usr(input: &str) -> IResult<&str, ast::Instruction> {
+    formats::no_arg(String::from("usr"), || ast::Instruction {
+        kind: ast::InstructionKind::Usr,
+    })(input)
+}
+
 fn xor(input: &str) -> IResult<&str, ast::Instruction> {
     alt((
         formats::lit_reg(String::from("xor"), |literal_expr, register| {
@@ -346,6 +376,67 @@ fn xor(input: &str) -> IResult<&str, ast::Instruction> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn sys_test() {
+        assert_eq!(
+            instruction("sys $1"),
+            Ok((
+                "",
+                ast::Instruction {
+                    kind: ast::InstructionKind::SysLit(ast::Expr {
+                        kind: ast::ExprKind::HexLiteral(0x1)
+                    })
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn lpt_usr_test() {
+        assert_eq!(
+            instruction("lpt r1"),
+            Ok((
+                "",
+                ast::Instruction {
+                    kind: ast::InstructionKind::LptReg(ast::Register::R1)
+                }
+            ))
+        );
+        assert_eq!(
+            instruction("usr"),
+            Ok((
+                "",
+                ast::Instruction {
+                    kind: ast::InstructionKind::Usr
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn int_test() {
+        assert_eq!(
+            instruction("int $3"),
+            Ok((
+                "",
+                ast::Instruction {
+                    kind: ast::InstructionKind::IntLit(ast::Expr {
+                        kind: ast::ExprKind::HexLiteral(0x3)
+                    })
+                }
+            ))
+        );
+        assert_eq!(
+            instruction("rti"),
+            Ok((
+                "",
+                ast::Instruction {
+                    kind: ast::InstructionKind::RetInt
+                }
+            ))
+        );
+    }
+
     #[test]
     fn mov_lit_mem_test() {
         assert_eq!(
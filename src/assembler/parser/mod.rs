@@ -1,4 +1,120 @@
+use crate::assembler::error::{AsmError, Span};
+use nom::{error::ErrorKind, Err};
+
 pub mod ast;
 pub mod expressions;
 pub mod instructions;
-pub mod types;
\ No newline at end of file
+pub mod statements;
+pub mod types;
+
+/// Parses a single instruction, failing if any input is left over.
+pub fn parse_instruction(input: &str) -> Result<ast::Instruction, AsmError> {
+    match instructions::instruction(input) {
+        Ok(("", instruction)) => Ok(instruction),
+        Ok((remaining, _)) => Err(AsmError::TrailingInput {
+            span: Span::at(input, remaining),
+        }),
+        Err(err) => Err(parse_error(input, err)),
+    }
+}
+
+/// Parses a program made of one statement per line. Blank lines are skipped.
+pub fn parse_program(input: &str) -> Result<Vec<ast::Statement>, AsmError> {
+    let mut statements = Vec::new();
+    let mut remaining = input.trim_start();
+
+    while !remaining.is_empty() {
+        match statements::statement(remaining) {
+            Ok((rest, statement)) => {
+                if !rest.is_empty() && !rest.starts_with(['\n', '\r']) {
+                    return Err(AsmError::TrailingInput {
+                        span: Span::at(input, rest),
+                    });
+                }
+
+                statements.push(statement);
+                remaining = rest.trim_start();
+            }
+            Err(err) => return Err(parse_error(input, err)),
+        }
+    }
+
+    Ok(statements)
+}
+
+/// Converts a nom error on the given source to an `AsmError`.
+fn parse_error(input: &str, err: Err<(&str, ErrorKind)>) -> AsmError {
+    match err {
+        Err::Error((remaining, kind)) | Err::Failure((remaining, kind)) => AsmError::Syntax {
+            span: Span::at(input, remaining),
+            kind,
+        },
+        Err::Incomplete(_) => AsmError::UnexpectedEnd {
+            span: Span::at(input, ""),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_instruction_test() {
+        assert_eq!(
+            parse_instruction("hlt"),
+            Ok(ast::Instruction {
+                kind: ast::InstructionKind::Hlt
+            })
+        );
+        assert_eq!(
+            parse_instruction("psh r1 r2"),
+            Err(AsmError::TrailingInput {
+                span: Span {
+                    start: 7,
+                    end: 9,
+                    line: 1,
+                    column: 8
+                }
+            })
+        );
+        assert_eq!(
+            parse_instruction("nop"),
+            Err(AsmError::Syntax {
+                span: Span {
+                    start: 0,
+                    end: 3,
+                    line: 1,
+                    column: 1
+                },
+                kind: ErrorKind::Tag
+            })
+        );
+    }
+
+    #[test]
+    fn parse_program_test() {
+        let statements = parse_program("mov $1, r1\n\n.bank $2\n  fcl $3, &8000  \nhlt\n").unwrap();
+        assert_eq!(statements.len(), 4);
+        assert_eq!(
+            statements[1],
+            ast::Statement {
+                kind: ast::StatementKind::Bank(ast::Expr {
+                    kind: ast::ExprKind::HexLiteral(0x2)
+                })
+            }
+        );
+
+        assert_eq!(
+            parse_program("hlt\nhlt hlt"),
+            Err(AsmError::TrailingInput {
+                span: Span {
+                    start: 8,
+                    end: 11,
+                    line: 2,
+                    column: 5
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use crate::assembler::parser::{ast, expressions, instructions};
+use nom::{
+    branch::alt,
+    bytes::complete::tag_no_case,
+    character::complete::{char, space0, space1},
+    combinator::map,
+    sequence::{delimited, separated_pair, tuple},
+    IResult,
+};
+
+pub fn statement(input: &str) -> IResult<&str, ast::Statement> {
+    alt((
+        bank,
+        far_call,
+        map(instructions::instruction, |instruction| ast::Statement {
+            kind: ast::StatementKind::Instruction(instruction),
+        }),
+    ))(input)
+}
+
+fn bank(input: &str) -> IResult<&str, ast::Statement> {
+    map(
+        delimited(
+            tuple((tag_no_case(".bank"), space1)),
+            expressions::literal_expr,
+            space0,
+        ),
+        |literal_expr| ast::Statement {
+            kind: ast::StatementKind::Bank(literal_expr),
+        },
+    )(input)
+}
+
+fn far_call(input: &str) -> IResult<&str, ast::Statement> {
+    map(
+        delimited(
+            tuple((tag_no_case("fcl"), space1)),
+            separated_pair(
+                expressions::literal_expr,
+                delimited(space0, char(','), space0),
+                expressions::address_expr,
+            ),
+            space0,
+        ),
+        |(literal_expr, address_expr)| ast::Statement {
+            kind: ast::StatementKind::FarCall(literal_expr, address_expr),
+        },
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_test() {
+        assert_eq!(
+            statement(".BANK $1f"),
+            Ok((
+                "",
+                ast::Statement {
+                    kind: ast::StatementKind::Bank(ast::Expr {
+                        kind: ast::ExprKind::HexLiteral(0x1F)
+                    })
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn far_call_test() {
+        assert_eq!(
+            statement("fcl $2, &8000"),
+            Ok((
+                "",
+                ast::Statement {
+                    kind: ast::StatementKind::FarCall(
+                        ast::Expr {
+                            kind: ast::ExprKind::HexLiteral(0x2)
+                        },
+                        ast::Expr {
+                            kind: ast::ExprKind::Address(0x8000)
+                        }
+                    )
+                }
+            ))
+        );
+    }
+}
@@ -64,6 +64,7 @@ pub fn register(input: &str) -> IResult<&str, ast::Register> {
         value(ast::Register::Fp, tag_no_case("fp")),
         value(ast::Register::Ip, tag_no_case("ip")),
         value(ast::Register::Acc, tag_no_case("acc")),
+        value(ast::Register::Im, tag_no_case("im")),
     ))(input)
 }
 
@@ -1,4 +1,4 @@
-use std::process;
+use std::{error::Error, process};
 /*use virtual_machine16_bit::virtual_machine::{
     cpu::CPU, device::Device, instructions, memory::Memory, memory_mapper::MemoryMapper,
     screen_device::ScreenDevice,
@@ -15,13 +15,11 @@ fn main() {
     });
 }
 
-fn run() -> Result<(), String> {
+fn run() -> Result<(), Box<dyn Error>> {
     
     let test = "hlt";
     
-    let ast = parser::instructions::instruction(test)
-        .map(|parser_result| parser_result.1)
-        .map_err(|err| format!("Unable to parse the instruction: {}", err))?;
+    let ast = parser::parse_instruction(test)?;
 
     println!("{}", test);
     println!("{:#?}", ast);
@@ -0,0 +1,265 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+};
+use std::io::{self, Write};
+
+/// Number of channels, in order: square, triangle and noise.
+pub const CHANNEL_COUNT: usize = 3;
+/// Number of bytes between the registers of consecutive channels.
+pub const CHANNEL_STRIDE: usize = 0x08;
+
+/// Frequency register of a channel, in hertz.
+pub const CHANNEL_FREQUENCY: usize = 0x00;
+/// Volume register of a channel, from 0 to 255.
+pub const CHANNEL_VOLUME: usize = 0x02;
+/// Gate register of a channel: the channel is heard while bit 0 is set.
+pub const CHANNEL_GATE: usize = 0x04;
+
+/// Index of the square wave channel.
+pub const SQUARE_CHANNEL: usize = 0;
+/// Index of the triangle wave channel.
+pub const TRIANGLE_CHANNEL: usize = 1;
+/// Index of the noise channel.
+pub const NOISE_CHANNEL: usize = 2;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Channel {
+    frequency: u16,
+    volume: u16,
+    gate: u16,
+    /// Position in the current period, from 0 to 1.
+    phase: f64,
+}
+
+/// A tone generator rendering its output to 16-bit mono samples.
+///
+/// The CPU cycles are the timebase: every tick renders the samples covering
+/// the elapsed cycles, given how many cycles the CPU runs per second. The same
+/// program therefore always renders the same samples.
+#[derive(Clone, Debug)]
+pub struct AudioDevice {
+    channels: [Channel; CHANNEL_COUNT],
+    clock_rate: u64,
+    sample_rate: u32,
+    cycles: u64,
+    samples: Vec<i16>,
+    noise: u16,
+}
+
+impl AudioDevice {
+    /// Creates a device for a CPU running `clock_rate` cycles per second.
+    pub fn new(clock_rate: u64, sample_rate: u32) -> AudioDevice {
+        AudioDevice {
+            channels: [Channel::default(); CHANNEL_COUNT],
+            clock_rate,
+            sample_rate,
+            cycles: 0,
+            samples: Vec::new(),
+            noise: 1,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the samples rendered so far.
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    /// Writes the samples rendered so far as a 16-bit mono PCM WAV file.
+    pub fn write_wav<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let data_size = (self.samples.len() * 2) as u32;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + data_size).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM, one channel
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * 2).to_le_bytes())?;
+        // Bytes per frame, bits per sample
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&data_size.to_le_bytes())?;
+
+        for sample in &self.samples {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the output of every channel mixed together, then advances them by one sample.
+    fn render_sample(&mut self) -> i16 {
+        let mut mix = 0.0;
+
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            let level = match index {
+                SQUARE_CHANNEL if channel.phase < 0.5 => 1.0,
+                SQUARE_CHANNEL => -1.0,
+                TRIANGLE_CHANNEL => 4.0 * (channel.phase - 0.5).abs() - 1.0,
+                _ if self.noise & 1 != 0 => 1.0,
+                _ => -1.0,
+            };
+
+            if channel.gate & 1 != 0 {
+                mix += level * f64::from(channel.volume.min(255)) / 255.0;
+            }
+
+            channel.phase += f64::from(channel.frequency) / f64::from(self.sample_rate);
+            while channel.phase >= 1.0 {
+                channel.phase -= 1.0;
+
+                // The 15-bit shift register of the noise advances once per period
+                if index == NOISE_CHANNEL {
+                    let feedback = (self.noise ^ self.noise >> 1) & 1;
+                    self.noise = self.noise >> 1 | feedback << 14;
+                }
+            }
+        }
+
+        (mix / CHANNEL_COUNT as f64 * f64::from(i16::MAX)) as i16
+    }
+
+    fn register(&mut self, address: usize) -> Result<&mut u16, VmError> {
+        let channel = self
+            .channels
+            .get_mut(address / CHANNEL_STRIDE)
+            .ok_or(VmError::OutOfBounds { address })?;
+
+        match address % CHANNEL_STRIDE {
+            CHANNEL_FREQUENCY => Ok(&mut channel.frequency),
+            CHANNEL_VOLUME => Ok(&mut channel.volume),
+            CHANNEL_GATE => Ok(&mut channel.gate),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+}
+
+impl WordRegisters for AudioDevice {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        self.register(address).map(|register| *register)
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        *self.register(address)? = value;
+        Ok(())
+    }
+}
+
+impl Device for AudioDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.set_register_u8(address, value)
+    }
+
+    fn reset(&mut self) {
+        *self = AudioDevice::new(self.clock_rate, self.sample_rate);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+
+        let due = u128::from(self.cycles) * u128::from(self.sample_rate)
+            / u128::from(self.clock_rate.max(1));
+        while (self.samples.len() as u128) < due {
+            let sample = self.render_sample();
+            self.samples.push(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a device rendering one sample every 10 cycles.
+    fn audio() -> AudioDevice {
+        AudioDevice::new(80, 8)
+    }
+
+    fn play(audio: &mut AudioDevice, channel: usize, frequency: u16, volume: u16) {
+        let base = channel * CHANNEL_STRIDE;
+        audio.set_u16(base + CHANNEL_FREQUENCY, frequency).unwrap();
+        audio.set_u16(base + CHANNEL_VOLUME, volume).unwrap();
+        audio.set_u16(base + CHANNEL_GATE, 1).unwrap();
+    }
+
+    #[test]
+    fn square_test() {
+        let mut audio = audio();
+        play(&mut audio, SQUARE_CHANNEL, 2, 255);
+
+        audio.tick(35);
+        assert_eq!(audio.samples().len(), 3);
+        audio.tick(45);
+
+        let high = i16::MAX / 3;
+        assert_eq!(
+            audio.samples(),
+            &[high, high, -high, -high, high, high, -high, -high]
+        );
+    }
+
+    #[test]
+    fn triangle_and_gate_test() {
+        let mut audio = audio();
+        play(&mut audio, TRIANGLE_CHANNEL, 2, 255);
+        audio.tick(40);
+        audio
+            .set_u8(TRIANGLE_CHANNEL * CHANNEL_STRIDE + CHANNEL_GATE + 1, 0)
+            .unwrap();
+        audio.tick(20);
+
+        let high = i16::MAX / 3;
+        assert_eq!(audio.samples(), &[high, 0, -high, 0, 0, 0]);
+    }
+
+    #[test]
+    fn noise_test() {
+        let mut first = audio();
+        let mut second = audio();
+        play(&mut first, NOISE_CHANNEL, 8, 128);
+        play(&mut second, NOISE_CHANNEL, 8, 128);
+        first.tick(800);
+        second.tick(800);
+
+        assert_eq!(first.samples(), second.samples());
+        assert!(first.samples().iter().any(|&sample| sample > 0));
+        assert!(first.samples().iter().any(|&sample| sample < 0));
+    }
+
+    #[test]
+    fn wav_test() {
+        let mut audio = audio();
+        play(&mut audio, SQUARE_CHANNEL, 2, 255);
+        audio.tick(20);
+
+        let mut wav = Vec::new();
+        audio.write_wav(&mut wav).unwrap();
+
+        assert_eq!(wav.len(), 44 + 4);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[24..28], &8u32.to_le_bytes());
+        assert_eq!(&wav[40..44], &4u32.to_le_bytes());
+        assert_eq!(&wav[44..46], &(i16::MAX / 3).to_le_bytes());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+};
+use std::{cell::RefCell, rc::Rc};
+
+struct Banks {
+    bytes: Box<[u8]>,
+    bank_size: usize,
+    bank_count: usize,
+    selected: usize,
+}
+
+impl Banks {
+    fn offset(&self, address: usize, length: usize) -> Result<usize, VmError> {
+        if address + length > self.bank_size {
+            return Err(VmError::OutOfBounds { address });
+        }
+
+        Ok(self.selected * self.bank_size + address)
+    }
+}
+
+/// A backing store larger than the address space, seen through a window one bank wide.
+///
+/// The window and the bank-select register are separate devices, so that they
+/// can be mapped at unrelated addresses. Code switching banks must not run from
+/// the window itself, since the instructions following the switch would come
+/// from the new bank.
+#[derive(Clone)]
+pub struct BankedMemory {
+    banks: Rc<RefCell<Banks>>,
+}
+
+impl BankedMemory {
+    pub fn new(bank_size: usize, bank_count: usize) -> BankedMemory {
+        BankedMemory {
+            banks: Rc::new(RefCell::new(Banks {
+                bytes: vec![0; bank_size * bank_count].into_boxed_slice(),
+                bank_size,
+                bank_count,
+                selected: 0,
+            })),
+        }
+    }
+
+    /// Returns the device showing the selected bank.
+    pub fn window(&self) -> BankWindow {
+        BankWindow {
+            banks: self.banks.clone(),
+        }
+    }
+
+    /// Returns the 16-bit register selecting the bank shown by the window.
+    pub fn bank_select(&self) -> BankSelect {
+        BankSelect {
+            banks: self.banks.clone(),
+        }
+    }
+
+    pub fn bank_size(&self) -> usize {
+        self.banks.borrow().bank_size
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.borrow().bank_count
+    }
+
+    pub fn selected_bank(&self) -> usize {
+        self.banks.borrow().selected
+    }
+
+    /// Writes the given bytes at the given offset of a bank, whichever bank is selected.
+    pub fn load_bank(&self, bank: usize, offset: usize, bytes: &[u8]) -> Result<(), VmError> {
+        let mut banks = self.banks.borrow_mut();
+
+        if bank >= banks.bank_count || offset + bytes.len() > banks.bank_size {
+            return Err(VmError::OutOfBounds { address: offset });
+        }
+
+        let start = bank * banks.bank_size + offset;
+        banks.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// The window of a `BankedMemory`, showing the selected bank.
+pub struct BankWindow {
+    banks: Rc<RefCell<Banks>>,
+}
+
+impl Device for BankWindow {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        let banks = self.banks.borrow();
+        let offset = banks.offset(address, 2)?;
+
+        Ok(u16::from_be_bytes([
+            banks.bytes[offset],
+            banks.bytes[offset + 1],
+        ]))
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        let banks = self.banks.borrow();
+        let offset = banks.offset(address, 1)?;
+
+        Ok(banks.bytes[offset])
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        let mut banks = self.banks.borrow_mut();
+        let offset = banks.offset(address, 2)?;
+
+        banks.bytes[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        let mut banks = self.banks.borrow_mut();
+        let offset = banks.offset(address, 1)?;
+
+        banks.bytes[offset] = value;
+        Ok(())
+    }
+}
+
+/// The bank-select register of a `BankedMemory`. Bank numbers wrap around the bank count.
+pub struct BankSelect {
+    banks: Rc<RefCell<Banks>>,
+}
+
+impl WordRegisters for BankSelect {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            0 => Ok(self.banks.borrow().selected as u16),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        match address {
+            0 => {
+                let mut banks = self.banks.borrow_mut();
+                banks.selected = usize::from(value) % banks.bank_count.max(1);
+                Ok(())
+            }
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+}
+
+impl Device for BankSelect {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.set_register_u8(address, value)
+    }
+
+    fn reset(&mut self) {
+        self.banks.borrow_mut().selected = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        cpu::CPU, instructions, memory::Memory, memory_mapper::MemoryMapper,
+    };
+
+    fn memory_mapper(banked_memory: &BankedMemory) -> MemoryMapper {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(banked_memory.window()), 0x8000, 0x8FFF, true)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(banked_memory.bank_select()), 0x9000, 0x9001, true)
+            .unwrap();
+        memory_mapper
+    }
+
+    #[test]
+    fn bank_switch_test() {
+        let banked_memory = BankedMemory::new(0x1000, 4);
+        let mut memory_mapper = memory_mapper(&banked_memory);
+
+        memory_mapper.set_u16(0x8FFE, 0x1234).unwrap();
+        memory_mapper.set_u16(0x9000, 3).unwrap();
+        assert_eq!(banked_memory.selected_bank(), 3);
+        assert_eq!(memory_mapper.get_u16(0x8FFE), Ok(0x0000));
+        memory_mapper.set_u8(0x8000, 0x56).unwrap();
+
+        memory_mapper.set_u8(0x9001, 4).unwrap();
+        assert_eq!(memory_mapper.get_u16(0x9000), Ok(0));
+        assert_eq!(memory_mapper.get_u16(0x8FFE), Ok(0x1234));
+
+        banked_memory.load_bank(3, 0x0001, &[0x78]).unwrap();
+        memory_mapper.set_u16(0x9000, 3).unwrap();
+        assert_eq!(memory_mapper.get_u16(0x8000), Ok(0x5678));
+        assert_eq!(
+            banked_memory.load_bank(4, 0, &[0]),
+            Err(VmError::OutOfBounds { address: 0 })
+        );
+    }
+
+    #[test]
+    fn far_call_test() {
+        let banked_memory = BankedMemory::new(0x1000, 4);
+        #[rustfmt::skip]
+        banked_memory.load_bank(1, 0, &[
+            instructions::MOV_LIT_REG, 0x00, 0x11, 1, // mov $11, acc
+            instructions::RET,
+        ]).unwrap();
+        #[rustfmt::skip]
+        banked_memory.load_bank(2, 0, &[
+            instructions::ADD_REG_REG, 2, 3, // add r1, r2
+            instructions::ADD_REG_REG, 4, 1, // add r3, acc
+            instructions::RET,
+        ]).unwrap();
+
+        let mut memory_mapper = memory_mapper(&banked_memory);
+
+        #[rustfmt::skip]
+        let program = [
+            instructions::MOV_LIT_MEM, 0x00, 0x01, 0x90, 0x00, // mov $1, &9000
+            instructions::MOV_LIT_REG, 0x00, 0x20, 2,          // mov $20, r1
+            instructions::MOV_LIT_REG, 0x00, 0x02, 3,          // mov $2, r2
+            instructions::MOV_LIT_REG, 0x03, 0x00, 4,          // mov $300, r3
+            instructions::PSH_LIT, 0x80, 0x00,                 // psh $8000
+            instructions::PSH_LIT, 0x00, 0x02,                 // psh $2
+            instructions::PSH_LIT, 0x00, 0x02,                 // psh $2
+            instructions::CAL_LIT, 0x01, 0x00,                 // cal $0100
+            instructions::HLT,
+        ];
+        // The sequence produced by `assembler::banks::far_call_trampoline(0x9000)`
+        #[rustfmt::skip]
+        let trampoline = [
+            instructions::MOV_MEM_REG, 0x90, 0x00, 1,         // mov &9000, acc
+            instructions::PSH_REG, 1,                         // psh acc
+            instructions::MOV_LIT_OFF_REG, 0x00, 0x18, 11, 1, // mov $18, &fp, acc
+            instructions::MOV_REG_MEM, 1, 0x90, 0x00,         // mov acc, &9000
+            instructions::MOV_LIT_OFF_REG, 0x00, 0x1A, 11, 1, // mov $1a, &fp, acc
+            instructions::PSH_LIT, 0x00, 0x00,                // psh $0
+            instructions::CAL_REG, 1,                         // cal acc
+            instructions::POP, 2,                             // pop r1
+            instructions::MOV_REG_MEM, 2, 0x90, 0x00,         // mov r1, &9000
+            instructions::RET,
+        ];
+        memory_mapper
+            .load_image(&program, 0x0000, "program")
+            .unwrap();
+        memory_mapper
+            .load_image(&trampoline, 0x0100, "far_call")
+            .unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("acc"), Ok(0x322));
+        assert_eq!(cpu.get_register("r1"), Ok(0x20));
+        assert_eq!(banked_memory.selected_bank(), 1);
+        assert_eq!(cpu.get_register("sp"), Ok(0xFFFE));
+    }
+}
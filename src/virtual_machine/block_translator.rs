@@ -0,0 +1,307 @@
+use crate::virtual_machine::{
+    cpu::CPU,
+    error::VmError,
+    instruction_cache::{Instruction, MAX_INSTRUCTION_LENGTH},
+    instructions,
+    memory_mapper::PAGE_SIZE,
+    registers::Register,
+};
+use std::rc::Rc;
+
+/// Number of addresses in the 16-bit address space.
+const ADDRESS_COUNT: usize = 0x10000;
+
+/// Maximum number of instructions in a block.
+const MAX_BLOCK_LENGTH: usize = 32;
+
+/// A translated instruction. Returns true if the CPU should halt.
+pub type Operation = Box<dyn Fn(&mut CPU) -> Result<bool, VmError>>;
+
+/// An instruction of a block, ready to be executed without decoding it.
+pub struct TranslatedInstruction {
+    pub operation: Operation,
+    pub address: u16,
+    /// The address of the next instruction of the block.
+    pub next: u16,
+    pub cycles: u64,
+}
+
+/// A straight-line run of instructions. Only its last instruction can jump.
+pub struct Block {
+    pub instructions: Vec<TranslatedInstruction>,
+    /// The address of the last byte of the block.
+    pub end: usize,
+}
+
+/// Keeps the blocks translated at each address.
+/// The owner is responsible for invalidating the blocks whose bytes are written.
+pub struct BlockCache {
+    entries: Box<[Option<Rc<Block>>]>,
+}
+
+impl Default for BlockCache {
+    fn default() -> BlockCache {
+        BlockCache::new()
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        BlockCache {
+            entries: vec![None; ADDRESS_COUNT].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the block starting at the given address.
+    pub fn get(&self, address: u16) -> Option<Rc<Block>> {
+        self.entries[address as usize].clone()
+    }
+
+    /// Records the block starting at the given address.
+    pub fn insert(&mut self, address: u16, block: Rc<Block>) {
+        self.entries[address as usize] = Some(block);
+    }
+
+    /// Removes every block that has a byte in the given mapper page.
+    pub fn invalidate_page(&mut self, page: usize) {
+        // Blocks start at most one page before the last page they cover
+        let page_start = page * PAGE_SIZE;
+        let start = page_start.saturating_sub(PAGE_SIZE);
+        let end = (page_start + PAGE_SIZE).min(ADDRESS_COUNT);
+
+        for entry in self.entries[start..end].iter_mut() {
+            if matches!(entry, Some(block) if block.end >= page_start) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// Removes every block.
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+/// Returns true if the instruction may change the flow of execution, the mode or the address space,
+/// so that it must be the last of its block.
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JmpLit(..)
+            | Instruction::JmpReg(..)
+            | Instruction::CalLit(_)
+            | Instruction::CalReg(_)
+            | Instruction::Ret
+            | Instruction::Int(_)
+            | Instruction::Sys(_)
+            | Instruction::RetInt
+            | Instruction::Lpt(_)
+            | Instruction::Usr
+            | Instruction::Hlt
+    )
+}
+
+/// Decodes the instruction at the given physical address without executing it.
+/// Returns the opcode, the instruction and its length.
+fn decode_at(cpu: &mut CPU, address: u16) -> Result<(u8, Instruction, u16), VmError> {
+    let ip = cpu.registers().get(Register::Ip);
+    cpu.registers_mut().set(Register::Ip, address);
+
+    let decoded = cpu.fetch().and_then(|opcode| {
+        let instruction = cpu.decode(opcode)?;
+        let length = cpu.registers().get(Register::Ip) - address;
+        Ok((opcode, instruction, length))
+    });
+
+    cpu.registers_mut().set(Register::Ip, ip);
+    decoded
+}
+
+/// Translates the instructions starting at the given address until one ends the block.
+/// The block stays in the page of its first instruction, except for the bytes of its last one.
+/// Returns None if the first instruction cannot be translated, so that it must be interpreted.
+pub fn translate_block(cpu: &mut CPU, start: u16) -> Option<Block> {
+    // Decoding must not read devices, since reads may have side effects
+    if !cpu.memory().is_plain_memory(start as usize) {
+        return None;
+    }
+
+    let page = start as usize / PAGE_SIZE;
+    let mut instructions = Vec::new();
+    let mut address = start;
+    let mut end = start as usize;
+
+    while instructions.len() < MAX_BLOCK_LENGTH && address as usize / PAGE_SIZE == page {
+        if !cpu
+            .memory()
+            .is_plain_memory(address as usize + MAX_INSTRUCTION_LENGTH - 1)
+        {
+            break;
+        }
+
+        let (opcode, instruction, length) = match decode_at(cpu, address) {
+            Ok(decoded) => decoded,
+            // The interpreter reports the error once the instruction is reached
+            Err(_) => break,
+        };
+
+        instructions.push(TranslatedInstruction {
+            operation: translate(address, instruction),
+            address,
+            next: address + length,
+            cycles: instructions::cycle_cost(opcode),
+        });
+        end = address as usize + length as usize - 1;
+
+        if ends_block(&instruction) {
+            break;
+        }
+        address += length;
+    }
+
+    if instructions.is_empty() {
+        None
+    } else {
+        Some(Block { instructions, end })
+    }
+}
+
+/// Translates the given instruction into a closure with its operands bound.
+/// Instructions that are not performance-sensitive are executed by the CPU.
+pub fn translate(address: u16, instruction: Instruction) -> Operation {
+    // Writing im is only allowed in supervisor mode, which the CPU checks
+    if let Some((_, Register::Im)) = instruction.register_write() {
+        return Box::new(move |cpu| cpu.execute_instruction(address, instruction));
+    }
+
+    match instruction {
+        Instruction::MovLitReg(literal, register) => Box::new(move |cpu| {
+            cpu.registers_mut().set(register, literal);
+            Ok(false)
+        }),
+        Instruction::MovRegReg(register_from, register_to) => Box::new(move |cpu| {
+            let value = cpu.registers().get(register_from);
+            cpu.registers_mut().set(register_to, value);
+            Ok(false)
+        }),
+        Instruction::AddRegReg(register1, register2) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            let value = registers.get(register1) + registers.get(register2);
+            registers.set(Register::Acc, value);
+            Ok(false)
+        }),
+        Instruction::AddLitReg(literal, register) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            let value = literal + registers.get(register);
+            registers.set(Register::Acc, value);
+            Ok(false)
+        }),
+        Instruction::SubLitReg(literal, register) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            let value = registers.get(register) - literal;
+            registers.set(Register::Acc, value);
+            Ok(false)
+        }),
+        Instruction::SubRegReg(register1, register2) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            let value = registers.get(register1) - registers.get(register2);
+            registers.set(Register::Acc, value);
+            Ok(false)
+        }),
+        Instruction::IncReg(register) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            let value = registers.get(register) + 1;
+            registers.set(register, value);
+            Ok(false)
+        }),
+        Instruction::DecReg(register) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            let value = registers.get(register) - 1;
+            registers.set(register, value);
+            Ok(false)
+        }),
+        Instruction::JmpLit(condition, literal, target) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            if condition.holds(literal, registers.get(Register::Acc)) {
+                registers.set(Register::Ip, target);
+            }
+            Ok(false)
+        }),
+        Instruction::JmpReg(condition, register, target) => Box::new(move |cpu| {
+            let registers = cpu.registers_mut();
+            if condition.holds(registers.get(register), registers.get(Register::Acc)) {
+                registers.set(Register::Ip, target);
+            }
+            Ok(false)
+        }),
+        _ => Box::new(move |cpu| cpu.execute_instruction(address, instruction)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{memory::Memory, memory_mapper::MemoryMapper};
+
+    fn cpu_with_program(program: &[u8], address: usize) -> CPU {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0, 0xffff, false)
+            .unwrap();
+        memory_mapper
+            .load_image(program, address, "program")
+            .unwrap();
+
+        CPU::new(memory_mapper).unwrap()
+    }
+
+    #[test]
+    fn translate_block_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::MOV_LIT_REG, 0x12, 0x34, 3,         // mov $1234, r2
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00, // jne $ffff, &0000
+            instructions::HLT,
+        ], 0x0000);
+
+        let block = translate_block(&mut cpu, 0x0000).unwrap();
+
+        let addresses: Vec<u16> = block.instructions.iter().map(|i| i.address).collect();
+        assert_eq!(addresses, vec![0x0000, 0x0002, 0x0006]);
+        assert_eq!(block.end, 0x000A);
+        assert_eq!(cpu.registers().get(Register::Ip), 0x0000);
+    }
+
+    #[test]
+    fn translate_block_page_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x12, 0x34, 3, // mov $1234, r2
+            instructions::INC_REG, 2,                 // inc r1
+        ], 0x00FD);
+
+        // The last instruction may straddle the page, but the next one starts a new block
+        let block = translate_block(&mut cpu, 0x00FD).unwrap();
+        assert_eq!(block.instructions.len(), 1);
+        assert_eq!(block.end, 0x0100);
+
+        let mut cache = BlockCache::new();
+        cache.insert(0x00FD, Rc::new(block));
+        cache.invalidate_page(0);
+        assert!(cache.get(0x00FD).is_none());
+
+        cache.insert(0x00FD, Rc::new(translate_block(&mut cpu, 0x00FD).unwrap()));
+        cache.invalidate_page(1);
+        assert!(cache.get(0x00FD).is_none());
+    }
+
+    #[test]
+    fn untranslatable_test() {
+        let mut cpu = cpu_with_program(&[0x00], 0x0000);
+
+        assert!(translate_block(&mut cpu, 0x0000).is_none());
+    }
+}
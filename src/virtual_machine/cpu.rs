@@ -1,580 +1,1179 @@
 use crate::virtual_machine::{
-    device::Device, instructions, memory::Memory, memory_mapper::MemoryMapper,
+    block_translator::{self, Block, BlockCache},
+    error::VmError,
+    instruction_cache::{Condition, DecodedInstruction, Instruction, InstructionCache},
+    instructions,
+    interrupts::{
+        IrqLine, DEFAULT_INTERRUPT_VECTOR_ADDRESS, GENERAL_PROTECTION_FAULT_VECTOR,
+        INTERRUPT_VECTOR_COUNT, PAGE_FAULT_VECTOR,
+    },
+    memory_mapper::MemoryMapper,
+    mmu::{Access, Mmu, Mode, MMU_PAGE_SIZE},
+    registers::{Register, RegisterFile},
 };
-use std::{collections::HashMap, fmt};
+use std::{
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A host function called by the `SYS` instruction. It gets its arguments from r1–r8 and returns its result in `acc`.
+/// A fault returned by the function restarts the `SYS` instruction once it is handled, so the function must
+/// check the guest memory it accesses with `CPU::check_bytes` before any side effect.
+pub type HostCall = Box<dyn FnMut(&mut CPU) -> Result<(), VmError>>;
 
-const REGISTER_NAMES: [&str; 12] = [
-    "ip", "acc", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "sp", "fp",
-];
+/// How far ahead of real time the CPU may run before the throttle sleeps.
+const THROTTLE_SLACK: Duration = Duration::from_millis(1);
+
+/// Keeps the CPU from running faster than a clock frequency.
+struct Throttle {
+    frequency: u64,
+    start: Instant,
+    start_cycles: u64,
+}
 
 pub struct CPU {
     memory: MemoryMapper,
-    register_map: HashMap<String, usize>,
-    registers: Memory,
+    registers: RegisterFile,
     stack_frame_size: u16,
+    halted: bool,
+    interrupt_vector_address: u16,
+    is_in_interrupt_handler: bool,
+    irq_line: IrqLine,
+    mmu: Mmu,
+    mode: Mode,
+    /// The mode to restore when returning from the current interrupt handler.
+    interrupted_mode: Mode,
+    host_calls: HashMap<u16, HostCall>,
+    exit_status: Option<u16>,
+    cycles: u64,
+    throttle: Option<Throttle>,
+    instruction_cache: Option<InstructionCache>,
+    block_cache: Option<BlockCache>,
 }
 
 impl CPU {
     /// Creates a new CPU instance with the given memory.
-    pub fn new(memory: MemoryMapper) -> Result<CPU, String> {
-        let register_map: HashMap<String, usize> = REGISTER_NAMES
-            .iter()
-            .enumerate()
-            .map(|(index, register_name)| (String::from(*register_name), index * 2))
-            .collect();
-
-        let registers = Memory::new(REGISTER_NAMES.len() * 2);
-
+    pub fn new(memory: MemoryMapper) -> Result<CPU, VmError> {
         let mut cpu = CPU {
             memory,
-            register_map,
-            registers,
+            registers: RegisterFile::new(),
             stack_frame_size: 0,
+            halted: false,
+            interrupt_vector_address: DEFAULT_INTERRUPT_VECTOR_ADDRESS,
+            is_in_interrupt_handler: false,
+            irq_line: IrqLine::new(),
+            mmu: Mmu::new(),
+            mode: Mode::Supervisor,
+            interrupted_mode: Mode::Supervisor,
+            host_calls: HashMap::new(),
+            exit_status: None,
+            cycles: 0,
+            throttle: None,
+            instruction_cache: None,
+            block_cache: None,
         };
 
-        cpu.set_register("sp", 0xffff - 1)?;
-        cpu.set_register("fp", 0xffff - 1)?;
+        // The devices keep the state the host set up before building the CPU
+        cpu.reset_state();
 
         Ok(cpu)
     }
 
-    /// Gets the value in the given register.
-    pub fn get_register(&self, name: &str) -> Result<u16, String> {
-        let index = self
-            .register_map
-            .get(name)
-            .ok_or(format!("get_register: No such register '{}'", name))?;
+    /// Returns the memory mapper the CPU is connected to.
+    pub fn memory(&self) -> &MemoryMapper {
+        &self.memory
+    }
+
+    /// Returns the memory mapper the CPU is connected to for modification, e.g. to load a program.
+    pub fn memory_mut(&mut self) -> &mut MemoryMapper {
+        &mut self.memory
+    }
+
+    /// Returns the register file.
+    pub fn registers(&self) -> &RegisterFile {
+        &self.registers
+    }
+
+    /// Returns the register file for modification.
+    pub fn registers_mut(&mut self) -> &mut RegisterFile {
+        &mut self.registers
+    }
+
+    /// Gets the value in the register with the given name.
+    pub fn get_register(&self, name: &str) -> Result<u16, VmError> {
+        let register: Register = name.parse()?;
+
+        Ok(self.registers.get(register))
+    }
+
+    /// Sets the given value to the register with the given name.
+    pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), VmError> {
+        let register: Register = name.parse()?;
+
+        self.registers.set(register, value);
+        Ok(())
+    }
+
+    /// Sets the address of the interrupt vector table.
+    pub fn set_interrupt_vector_address(&mut self, address: u16) {
+        self.interrupt_vector_address = address;
+    }
+
+    /// Returns a handle that devices can use to request hardware interrupts.
+    pub fn irq_line(&self) -> IrqLine {
+        self.irq_line.clone()
+    }
+
+    /// Returns the MMU translating the addresses of the CPU.
+    pub fn mmu(&self) -> &Mmu {
+        &self.mmu
+    }
+
+    /// Returns the MMU for modification, e.g. to enable it from the host.
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.mmu
+    }
 
-        Ok(self.registers.get_u16(*index)?)
+    /// Returns the current privilege level.
+    pub fn mode(&self) -> Mode {
+        self.mode
     }
 
-    /// Sets the given value to the given register.
-    pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), String> {
-        let index = self
-            .register_map
-            .get(name)
-            .ok_or(format!("set_register: No such register '{}'", name))?;
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
 
-        self.registers.set_u16(*index, value)
+    /// Registers the host function called by `SYS` with the given number, replacing any previous one.
+    pub fn register_host_call<F>(&mut self, number: u16, host_call: F)
+    where
+        F: FnMut(&mut CPU) -> Result<(), VmError> + 'static,
+    {
+        self.host_calls.insert(number, Box::new(host_call));
+    }
+
+    /// Halts the CPU with the given exit status, e.g. from a host call.
+    pub fn exit(&mut self, status: u16) {
+        self.halted = true;
+        self.exit_status = Some(status);
+    }
+
+    /// Returns the status given to `exit`, if it was called.
+    pub fn exit_status(&self) -> Option<u16> {
+        self.exit_status
+    }
+
+    /// Reads bytes starting at the given virtual address, with the permissions of the current mode.
+    pub fn read_bytes(&mut self, address: usize, length: usize) -> Result<Vec<u8>, VmError> {
+        (address..address + length)
+            .map(|address| self.read_u8(address, Access::Read))
+            .collect()
+    }
+
+    /// Fails with the page fault an access to any of the given bytes would raise, without accessing them.
+    pub fn check_bytes(
+        &mut self,
+        address: usize,
+        length: usize,
+        access: Access,
+    ) -> Result<(), VmError> {
+        let end = address + length;
+        let mut page_address = address;
+
+        while page_address < end {
+            self.mmu
+                .translate(&mut self.memory, page_address, access, self.mode)?;
+            page_address = (page_address / MMU_PAGE_SIZE + 1) * MMU_PAGE_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Writes bytes starting at the given virtual address, with the permissions of the current mode.
+    pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address =
+                self.mmu
+                    .translate(&mut self.memory, address + offset, Access::Write, self.mode)?;
+            self.memory.set_u8(address, *byte)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls the host function registered with the given number.
+    fn call_host(&mut self, number: u16) -> Result<(), VmError> {
+        let mut host_call = self
+            .host_calls
+            .remove(&number)
+            .ok_or(VmError::UnknownHostCall {
+                ip: self.registers.get(Register::Ip) - 3,
+                number,
+            })?;
+
+        let result = host_call(self);
+
+        // The host call may have registered a replacement for itself
+        self.host_calls.entry(number).or_insert(host_call);
+        result
+    }
+
+    /// Reads the u8 at the given virtual address.
+    fn read_u8(&mut self, address: usize, access: Access) -> Result<u8, VmError> {
+        let address = self
+            .mmu
+            .translate(&mut self.memory, address, access, self.mode)?;
+        self.memory.get_u8(address)
+    }
+
+    /// Reads the u16 at the given virtual address.
+    fn read_u16(&mut self, address: usize, access: Access) -> Result<u16, VmError> {
+        // The bytes of a u16 straddling two pages are translated separately
+        if self.mmu.is_enabled() && address % MMU_PAGE_SIZE == MMU_PAGE_SIZE - 1 {
+            return Ok(u16::from_be_bytes([
+                self.read_u8(address, access)?,
+                self.read_u8(address + 1, access)?,
+            ]));
+        }
+
+        let address = self
+            .mmu
+            .translate(&mut self.memory, address, access, self.mode)?;
+        self.memory.get_u16(address)
+    }
+
+    /// Writes the u16 at the given virtual address.
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        if self.mmu.is_enabled() && address % MMU_PAGE_SIZE == MMU_PAGE_SIZE - 1 {
+            // Both pages are checked before writing anything
+            let [high, low] = value.to_be_bytes();
+            let high_address =
+                self.mmu
+                    .translate(&mut self.memory, address, Access::Write, self.mode)?;
+            let low_address =
+                self.mmu
+                    .translate(&mut self.memory, address + 1, Access::Write, self.mode)?;
+
+            self.memory.set_u8(high_address, high)?;
+            return self.memory.set_u8(low_address, low);
+        }
+
+        let address = self
+            .mmu
+            .translate(&mut self.memory, address, Access::Write, self.mode)?;
+        self.memory.set_u16(address, value)
     }
 
     /// Fetches the next 8-bit instruction and increments the instruction pointer.
-    pub fn fetch(&mut self) -> Result<u8, String> {
-        let instruction_address = self.get_register("ip")?;
-        let instruction = self.memory.get_u8(instruction_address as usize)?;
-        self.set_register("ip", instruction_address + 1)?;
+    pub fn fetch(&mut self) -> Result<u8, VmError> {
+        let instruction_address = self.registers.get(Register::Ip);
+        let instruction = self.read_u8(instruction_address as usize, Access::Execute)?;
+        self.registers.set(Register::Ip, instruction_address + 1);
 
         Ok(instruction)
     }
 
     /// Fetches the next 16-bit instruction and increments the instruction pointer.
-    pub fn fetch16(&mut self) -> Result<u16, String> {
-        let instruction_address = self.get_register("ip")?;
-        let instruction = self.memory.get_u16(instruction_address as usize)?;
-        self.set_register("ip", instruction_address + 2)?;
+    pub fn fetch16(&mut self) -> Result<u16, VmError> {
+        let instruction_address = self.registers.get(Register::Ip);
+        let instruction = self.read_u16(instruction_address as usize, Access::Execute)?;
+        self.registers.set(Register::Ip, instruction_address + 2);
 
         Ok(instruction)
     }
 
-    /// Fetches the stored register index.
-    pub fn fetch_register_index(&mut self) -> Result<usize, String> {
-        Ok((self.fetch()? as usize % REGISTER_NAMES.len()) * 2)
+    /// Fetches the next byte and decodes it as a register.
+    pub fn fetch_register(&mut self) -> Result<Register, VmError> {
+        let ip = self.registers.get(Register::Ip);
+        let index = self.fetch()?;
+
+        Register::from_index(index).ok_or(VmError::InvalidRegister { ip, index })
     }
 
     /// Pushes the given value onto the stack and moves the stack pointer.
-    pub fn push(&mut self, value: u16) -> Result<(), String> {
-        let address = self.get_register("sp")?;
-        self.memory.set_u16(address as usize, value)?;
-        self.set_register("sp", address - 2)?;
+    pub fn push(&mut self, value: u16) -> Result<(), VmError> {
+        let address = self.registers.get(Register::Sp);
+        self.write_u16(address as usize, value)?;
+        self.registers.set(Register::Sp, address - 2);
         self.stack_frame_size += 2;
 
         Ok(())
     }
 
     /// Moves the stack pointer and returns the value at that memory location.
-    pub fn pop(&mut self) -> Result<u16, String> {
-        let next_sp_address = self.get_register("sp")? + 2;
-        self.set_register("sp", next_sp_address)?;
-        self.stack_frame_size -= 2;
+    pub fn pop(&mut self) -> Result<u16, VmError> {
+        let next_sp_address = self.registers.get(Register::Sp) + 2;
+        self.registers.set(Register::Sp, next_sp_address);
+        self.stack_frame_size = self.stack_frame_size.wrapping_sub(2);
 
-        Ok(self.memory.get_u16(next_sp_address as usize)?)
+        self.read_u16(next_sp_address as usize, Access::Read)
     }
 
     /// Pushes the current CPU state to the stack and moves the frame pointer.
-    pub fn push_state(&mut self) -> Result<(), String> {
-        self.push(self.get_register("r1")?)?;
-        self.push(self.get_register("r2")?)?;
-        self.push(self.get_register("r3")?)?;
-        self.push(self.get_register("r4")?)?;
-        self.push(self.get_register("r5")?)?;
-        self.push(self.get_register("r6")?)?;
-        self.push(self.get_register("r7")?)?;
-        self.push(self.get_register("r8")?)?;
-        self.push(self.get_register("ip")?)?;
+    pub fn push_state(&mut self) -> Result<(), VmError> {
+        self.push(self.registers.get(Register::R1))?;
+        self.push(self.registers.get(Register::R2))?;
+        self.push(self.registers.get(Register::R3))?;
+        self.push(self.registers.get(Register::R4))?;
+        self.push(self.registers.get(Register::R5))?;
+        self.push(self.registers.get(Register::R6))?;
+        self.push(self.registers.get(Register::R7))?;
+        self.push(self.registers.get(Register::R8))?;
+        self.push(self.registers.get(Register::Ip))?;
         self.push(self.stack_frame_size + 2)?;
 
-        self.set_register("fp", self.get_register("sp")?)?;
+        self.registers
+            .set(Register::Fp, self.registers.get(Register::Sp));
         self.stack_frame_size = 0;
 
         Ok(())
     }
 
     /// Pops the CPU state from the stack and restores the CPU state.
-    pub fn pop_state(&mut self) -> Result<(), String> {
-        let frame_pointer_address = self.get_register("fp")?;
-        self.set_register("sp", frame_pointer_address)?;
+    pub fn pop_state(&mut self) -> Result<(), VmError> {
+        let frame_pointer_address = self.registers.get(Register::Fp);
+        self.registers.set(Register::Sp, frame_pointer_address);
 
         self.stack_frame_size = self.pop()?;
         let stack_frame_size = self.stack_frame_size;
 
         let value = self.pop()?;
-        self.set_register("ip", value)?;
+        self.registers.set(Register::Ip, value);
         let value = self.pop()?;
-        self.set_register("r8", value)?;
+        self.registers.set(Register::R8, value);
         let value = self.pop()?;
-        self.set_register("r7", value)?;
+        self.registers.set(Register::R7, value);
         let value = self.pop()?;
-        self.set_register("r6", value)?;
+        self.registers.set(Register::R6, value);
         let value = self.pop()?;
-        self.set_register("r5", value)?;
+        self.registers.set(Register::R5, value);
         let value = self.pop()?;
-        self.set_register("r4", value)?;
+        self.registers.set(Register::R4, value);
         let value = self.pop()?;
-        self.set_register("r3", value)?;
+        self.registers.set(Register::R3, value);
         let value = self.pop()?;
-        self.set_register("r2", value)?;
+        self.registers.set(Register::R2, value);
         let value = self.pop()?;
-        self.set_register("r1", value)?;
+        self.registers.set(Register::R1, value);
 
         let args_length = self.pop()?;
         for _ in 0..args_length {
             self.pop()?;
         }
 
-        self.set_register("fp", frame_pointer_address + stack_frame_size)?;
+        self.registers
+            .set(Register::Fp, frame_pointer_address + stack_frame_size);
 
         Ok(())
     }
 
+    /// Jumps to the handler of the given interrupt unless it is masked.
+    pub fn handle_interrupt(&mut self, value: u16) -> Result<(), VmError> {
+        let interrupt_vector_index = value % INTERRUPT_VECTOR_COUNT;
+        let is_unmasked = (1 << interrupt_vector_index) & self.registers.get(Register::Im) != 0;
+        if !is_unmasked {
+            return Ok(());
+        }
+
+        self.enter_interrupt(interrupt_vector_index)
+    }
+
+    /// Jumps to the handler of the given interrupt in supervisor mode.
+    /// The interrupt vector table is read from physical memory.
+    ///
+    /// The state is pushed with the rights of the interrupted mode, since user mode
+    /// controls `sp`. Fails with `VmError::DoubleFault` if the stack denies the push.
+    fn enter_interrupt(&mut self, interrupt_vector_index: u16) -> Result<(), VmError> {
+        let address_pointer =
+            self.interrupt_vector_address as usize + interrupt_vector_index as usize * 2;
+        let address = self.memory.get_u16(address_pointer)?;
+
+        let registers = self.registers.clone();
+        let stack_frame_size = self.stack_frame_size;
+
+        // The handler is entered like a subroutine called with no arguments
+        if let Err(error) = self.push(0).and_then(|_| self.push_state()) {
+            self.registers = registers;
+            self.stack_frame_size = stack_frame_size;
+
+            return Err(match error {
+                VmError::PageFault { .. } => VmError::DoubleFault {
+                    vector: interrupt_vector_index,
+                    sp: self.registers.get(Register::Sp),
+                },
+                error => error,
+            });
+        }
+
+        self.interrupted_mode = self.mode;
+        self.mode = Mode::Supervisor;
+        self.is_in_interrupt_handler = true;
+        self.registers.set(Register::Ip, address);
+
+        Ok(())
+    }
+
+    /// Turns the given error into a fault interrupt if it has one, after restoring
+    /// the registers from before the faulting instruction.
+    /// Returns false if the error must be reported to the host instead.
+    fn raise_fault(
+        &mut self,
+        error: &VmError,
+        registers: RegisterFile,
+        stack_frame_size: u16,
+    ) -> Result<bool, VmError> {
+        // A fault in a handler cannot be serviced since handlers do not nest
+        if self.is_in_interrupt_handler {
+            return Ok(false);
+        }
+
+        let (interrupt_vector_index, info1, info2) = match *error {
+            VmError::PageFault { address, access } => {
+                (PAGE_FAULT_VECTOR, address as u16, access.code())
+            }
+            VmError::PrivilegedInstruction { ip, opcode } => {
+                (GENERAL_PROTECTION_FAULT_VECTOR, ip, u16::from(opcode))
+            }
+            _ => return Ok(false),
+        };
+
+        self.registers = registers;
+        self.stack_frame_size = stack_frame_size;
+        self.enter_interrupt(interrupt_vector_index)?;
+        self.registers.set(Register::R1, info1);
+        self.registers.set(Register::R2, info2);
+
+        Ok(true)
+    }
+
+    /// Fails if the CPU is in user mode.
+    fn check_privileged(&self, ip: u16, opcode: u8) -> Result<(), VmError> {
+        match self.mode {
+            Mode::Supervisor => Ok(()),
+            Mode::User => Err(VmError::PrivilegedInstruction { ip, opcode }),
+        }
+    }
+
+    /// Returns the mask of the hardware interrupts that would be serviced before the next instruction.
+    fn interrupt_requests(&self) -> u16 {
+        if self.is_in_interrupt_handler {
+            return 0;
+        }
+
+        (self.irq_line.pending() | self.memory.irq_pending()) & self.registers.get(Register::Im)
+    }
+
+    /// Services the lowest pending hardware interrupt that is not masked.
+    /// Returns true if an interrupt handler was entered.
+    fn handle_pending_interrupts(&mut self) -> Result<bool, VmError> {
+        let requests = self.interrupt_requests();
+        if requests == 0 {
+            return Ok(false);
+        }
+
+        let interrupt_vector_index = requests.trailing_zeros() as u16;
+        self.irq_line.clear(interrupt_vector_index);
+        self.handle_interrupt(interrupt_vector_index)?;
+        Ok(true)
+    }
+
     /// Executes the given instruction. Returns true if the CPU should halt.
-    pub fn execute(&mut self, instruction: u8) -> Result<bool, String> {
+    #[inline(always)]
+    pub fn execute(&mut self, opcode: u8) -> Result<bool, VmError> {
+        let address = self.registers.get(Register::Ip) - 1;
+        let instruction = self.decode(opcode)?;
+        self.execute_instruction(address, instruction)
+    }
+
+    /// Fetches the operands of the given instruction.
+    #[inline(always)]
+    pub fn decode(&mut self, opcode: u8) -> Result<Instruction, VmError> {
+        let instruction = match opcode {
+            instructions::MOV_LIT_REG => {
+                Instruction::MovLitReg(self.fetch16()?, self.fetch_register()?)
+            }
+            instructions::MOV_REG_REG => {
+                Instruction::MovRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::MOV_REG_MEM => {
+                Instruction::MovRegMem(self.fetch_register()?, self.fetch16()?)
+            }
+            instructions::MOV_MEM_REG => {
+                Instruction::MovMemReg(self.fetch16()?, self.fetch_register()?)
+            }
+            instructions::MOV_LIT_MEM => Instruction::MovLitMem(self.fetch16()?, self.fetch16()?),
+            instructions::MOV_REG_PTR_REG => {
+                Instruction::MovRegPtrReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::MOV_LIT_OFF_REG => Instruction::MovLitOffReg(
+                self.fetch16()?,
+                self.fetch_register()?,
+                self.fetch_register()?,
+            ),
+            instructions::ADD_REG_REG => {
+                Instruction::AddRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::ADD_LIT_REG => {
+                Instruction::AddLitReg(self.fetch16()?, self.fetch_register()?)
+            }
+            instructions::SUB_LIT_REG => {
+                Instruction::SubLitReg(self.fetch16()?, self.fetch_register()?)
+            }
+            instructions::SUB_REG_LIT => {
+                Instruction::SubRegLit(self.fetch_register()?, self.fetch16()?)
+            }
+            instructions::SUB_REG_REG => {
+                Instruction::SubRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::MUL_LIT_REG => {
+                Instruction::MulLitReg(self.fetch16()?, self.fetch_register()?)
+            }
+            instructions::MUL_REG_REG => {
+                Instruction::MulRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::INC_REG => Instruction::IncReg(self.fetch_register()?),
+            instructions::DEC_REG => Instruction::DecReg(self.fetch_register()?),
+            instructions::LSF_REG_LIT => {
+                Instruction::LsfRegLit(self.fetch_register()?, self.fetch()?)
+            }
+            instructions::LSF_REG_REG => {
+                Instruction::LsfRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::RSF_REG_LIT => {
+                Instruction::RsfRegLit(self.fetch_register()?, self.fetch()?)
+            }
+            instructions::RSF_REG_REG => {
+                Instruction::RsfRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::AND_REG_LIT => {
+                Instruction::AndRegLit(self.fetch_register()?, self.fetch16()?)
+            }
+            instructions::AND_REG_REG => {
+                Instruction::AndRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::OR_REG_LIT => {
+                Instruction::OrRegLit(self.fetch_register()?, self.fetch16()?)
+            }
+            instructions::OR_REG_REG => {
+                Instruction::OrRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::XOR_REG_LIT => {
+                Instruction::XorRegLit(self.fetch_register()?, self.fetch16()?)
+            }
+            instructions::XOR_REG_REG => {
+                Instruction::XorRegReg(self.fetch_register()?, self.fetch_register()?)
+            }
+            instructions::NOT => Instruction::Not(self.fetch_register()?),
+            instructions::JMP_NOT_EQ => self.decode_jump_literal(Condition::NotEqual)?,
+            instructions::JNE_REG => self.decode_jump_register(Condition::NotEqual)?,
+            instructions::JEQ_LIT => self.decode_jump_literal(Condition::Equal)?,
+            instructions::JEQ_REG => self.decode_jump_register(Condition::Equal)?,
+            instructions::JLT_LIT => self.decode_jump_literal(Condition::LessThan)?,
+            instructions::JLT_REG => self.decode_jump_register(Condition::LessThan)?,
+            instructions::JGT_LIT => self.decode_jump_literal(Condition::GreaterThan)?,
+            instructions::JGT_REG => self.decode_jump_register(Condition::GreaterThan)?,
+            instructions::JLE_LIT => self.decode_jump_literal(Condition::LessOrEqual)?,
+            instructions::JLE_REG => self.decode_jump_register(Condition::LessOrEqual)?,
+            instructions::JGE_LIT => self.decode_jump_literal(Condition::GreaterOrEqual)?,
+            instructions::JGE_REG => self.decode_jump_register(Condition::GreaterOrEqual)?,
+            instructions::PSH_LIT => Instruction::PshLit(self.fetch16()?),
+            instructions::PSH_REG => Instruction::PshReg(self.fetch_register()?),
+            instructions::POP => Instruction::Pop(self.fetch_register()?),
+            instructions::CAL_LIT => Instruction::CalLit(self.fetch16()?),
+            instructions::CAL_REG => Instruction::CalReg(self.fetch_register()?),
+            instructions::RET => Instruction::Ret,
+            instructions::INT => Instruction::Int(self.fetch16()?),
+            instructions::SYS_LIT => Instruction::Sys(self.fetch16()?),
+            instructions::RET_INT => Instruction::RetInt,
+            instructions::LPT_REG => Instruction::Lpt(self.fetch_register()?),
+            instructions::USR => Instruction::Usr,
+            instructions::HLT => Instruction::Hlt,
+            _ => {
+                return Err(VmError::IllegalOpcode {
+                    ip: self.registers.get(Register::Ip) - 1,
+                    opcode,
+                });
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    /// Fetches the operands of a jump comparing `acc` with a literal.
+    fn decode_jump_literal(&mut self, condition: Condition) -> Result<Instruction, VmError> {
+        Ok(Instruction::JmpLit(
+            condition,
+            self.fetch16()?,
+            self.fetch16()?,
+        ))
+    }
+
+    /// Fetches the operands of a jump comparing `acc` with a register.
+    fn decode_jump_register(&mut self, condition: Condition) -> Result<Instruction, VmError> {
+        Ok(Instruction::JmpReg(
+            condition,
+            self.fetch_register()?,
+            self.fetch16()?,
+        ))
+    }
+
+    /// Executes the given decoded instruction, which starts at the given address.
+    /// The instruction pointer must already point past it. Returns true if the CPU should halt.
+    #[inline(always)]
+    pub fn execute_instruction(
+        &mut self,
+        address: u16,
+        instruction: Instruction,
+    ) -> Result<bool, VmError> {
+        // Masking the interrupts would keep the timer from preempting user code
+        if let Some((opcode, Register::Im)) = instruction.register_write() {
+            self.check_privileged(address, opcode)?;
+        }
+
         match instruction {
             // Move literal into register
-            instructions::MOV_LIT_REG => {
-                let literal = self.fetch16()?;
-                let register = self.fetch_register_index()?;
-                self.registers.set_u16(register, literal)?;
+            Instruction::MovLitReg(literal, register) => {
+                self.registers.set(register, literal);
             }
 
             // Move register to register
-            instructions::MOV_REG_REG => {
-                let register_from = self.fetch_register_index()?;
-                let register_to = self.fetch_register_index()?;
-                let value = self.registers.get_u16(register_from)?;
-                self.registers.set_u16(register_to, value)?;
+            Instruction::MovRegReg(register_from, register_to) => {
+                let value = self.registers.get(register_from);
+                self.registers.set(register_to, value);
             }
 
             // Move register to memory
-            instructions::MOV_REG_MEM => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()? as usize;
-                let value = self.registers.get_u16(register)?;
-                self.memory.set_u16(address, value)?;
+            Instruction::MovRegMem(register, address) => {
+                let value = self.registers.get(register);
+                self.write_u16(address as usize, value)?;
             }
 
             // Move memory to register
-            instructions::MOV_MEM_REG => {
-                let address = self.fetch16()? as usize;
-                let register_to = self.fetch_register_index()?;
-                let value = self.memory.get_u16(address)?;
-                self.registers.set_u16(register_to, value)?;
+            Instruction::MovMemReg(address, register_to) => {
+                let value = self.read_u16(address as usize, Access::Read)?;
+                self.registers.set(register_to, value);
             }
 
             // Move literal to memory
-            instructions::MOV_LIT_MEM => {
-                let value = self.fetch16()?;
-                let address = self.fetch16()? as usize;
-                self.memory.set_u16(address, value)?;
+            Instruction::MovLitMem(value, address) => {
+                self.write_u16(address as usize, value)?;
             }
 
             // Move register* to register
-            instructions::MOV_REG_PTR_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let pointer = self.registers.get_u16(register1)? as usize;
-                let value = self.memory.get_u16(pointer)?;
-                self.registers.set_u16(register2, value)?;
+            Instruction::MovRegPtrReg(register1, register2) => {
+                let pointer = self.registers.get(register1) as usize;
+                let value = self.read_u16(pointer, Access::Read)?;
+                self.registers.set(register2, value);
             }
 
             // Move value at [literal + register] to register
-            instructions::MOV_LIT_OFF_REG => {
-                let base_address = self.fetch16()? as usize;
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let offset = self.registers.get_u16(register1)? as usize;
+            Instruction::MovLitOffReg(base_address, register1, register2) => {
+                let offset = self.registers.get(register1) as usize;
 
-                let value = self.memory.get_u16(base_address + offset)?;
-                self.registers.set_u16(register2, value)?;
+                let value = self.read_u16(base_address as usize + offset, Access::Read)?;
+                self.registers.set(register2, value);
             }
 
             // Add register to register
-            instructions::ADD_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
-                self.set_register("acc", register_value1 + register_value2)?;
+            Instruction::AddRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
+                self.registers
+                    .set(Register::Acc, register_value1 + register_value2);
             }
 
             // Add literal to register
-            instructions::ADD_LIT_REG => {
-                let literal = self.fetch16()?;
-                let register = self.fetch_register_index()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", literal + register_value)?;
+            Instruction::AddLitReg(literal, register) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, literal + register_value);
             }
 
             // Subtract literal from register
-            instructions::SUB_LIT_REG => {
-                let literal = self.fetch16()?;
-                let register = self.fetch_register_index()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", register_value - literal)?;
+            Instruction::SubLitReg(literal, register) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, register_value - literal);
             }
 
             // Subtract register from literal
-            instructions::SUB_REG_LIT => {
-                let register = self.fetch_register_index()?;
-                let literal = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", literal - register_value)?;
+            Instruction::SubRegLit(register, literal) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, literal - register_value);
             }
 
             // Subtract register from register
-            instructions::SUB_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
-                self.set_register("acc", register_value1 - register_value2)?;
+            Instruction::SubRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
+                self.registers
+                    .set(Register::Acc, register_value1 - register_value2);
             }
 
             // Multiply literal by register
-            instructions::MUL_LIT_REG => {
-                let literal = self.fetch16()?;
-                let register = self.fetch_register_index()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", literal * register_value)?;
+            Instruction::MulLitReg(literal, register) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, literal * register_value);
             }
 
             // Multiply register by register
-            instructions::MUL_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
-                self.set_register("acc", register_value1 * register_value2)?;
+            Instruction::MulRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
+                self.registers
+                    .set(Register::Acc, register_value1 * register_value2);
             }
 
             // Increment value in register (in place)
-            instructions::INC_REG => {
-                let register = self.fetch_register_index()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.registers.set_u16(register, register_value + 1)?;
+            Instruction::IncReg(register) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(register, register_value + 1);
             }
 
             // Decrement value in register (in place)
-            instructions::DEC_REG => {
-                let register = self.fetch_register_index()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.registers.set_u16(register, register_value - 1)?;
+            Instruction::DecReg(register) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(register, register_value - 1);
             }
 
             // Left shift register by literal (in place)
-            instructions::LSF_REG_LIT => {
-                let register = self.fetch_register_index()?;
-                let literal = self.fetch()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.registers
-                    .set_u16(register, register_value << literal)?;
+            Instruction::LsfRegLit(register, literal) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(register, register_value << literal);
             }
 
             // Left shift register by register (in place)
-            instructions::LSF_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
+            Instruction::LsfRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
                 self.registers
-                    .set_u16(register1, register_value1 << register_value2)?;
+                    .set(register1, register_value1 << register_value2);
             }
 
             // Right shift register by literal (in place)
-            instructions::RSF_REG_LIT => {
-                let register = self.fetch_register_index()?;
-                let literal = self.fetch()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.registers
-                    .set_u16(register, register_value >> literal)?;
+            Instruction::RsfRegLit(register, literal) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(register, register_value >> literal);
             }
 
             // Right shift register by register (in place)
-            instructions::RSF_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
+            Instruction::RsfRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
                 self.registers
-                    .set_u16(register1, register_value1 >> register_value2)?;
+                    .set(register1, register_value1 >> register_value2);
             }
 
             // And register with literal
-            instructions::AND_REG_LIT => {
-                let register = self.fetch_register_index()?;
-                let literal = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", register_value & literal)?;
+            Instruction::AndRegLit(register, literal) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, register_value & literal);
             }
 
             // And register with register
-            instructions::AND_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
-                self.set_register("acc", register_value1 & register_value2)?;
+            Instruction::AndRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
+                self.registers
+                    .set(Register::Acc, register_value1 & register_value2);
             }
 
             // Or register with literal
-            instructions::OR_REG_LIT => {
-                let register = self.fetch_register_index()?;
-                let literal = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", register_value | literal)?;
+            Instruction::OrRegLit(register, literal) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, register_value | literal);
             }
 
             // Or register with register
-            instructions::OR_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
-                self.set_register("acc", register_value1 | register_value2)?;
+            Instruction::OrRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
+                self.registers
+                    .set(Register::Acc, register_value1 | register_value2);
             }
 
             // Xor register with literal
-            instructions::XOR_REG_LIT => {
-                let register = self.fetch_register_index()?;
-                let literal = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", register_value ^ literal)?;
+            Instruction::XorRegLit(register, literal) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, register_value ^ literal);
             }
 
             // Xor register with register
-            instructions::XOR_REG_REG => {
-                let register1 = self.fetch_register_index()?;
-                let register2 = self.fetch_register_index()?;
-                let register_value1 = self.registers.get_u16(register1)?;
-                let register_value2 = self.registers.get_u16(register2)?;
-                self.set_register("acc", register_value1 ^ register_value2)?;
+            Instruction::XorRegReg(register1, register2) => {
+                let register_value1 = self.registers.get(register1);
+                let register_value2 = self.registers.get(register2);
+                self.registers
+                    .set(Register::Acc, register_value1 ^ register_value2);
             }
 
             // Not (invert) register
-            instructions::NOT => {
-                let register = self.fetch_register_index()?;
-                let register_value = self.registers.get_u16(register)?;
-                self.set_register("acc", !register_value)?;
+            Instruction::Not(register) => {
+                let register_value = self.registers.get(register);
+                self.registers.set(Register::Acc, !register_value);
             }
 
-            // Jump if literal not equal
-            instructions::JMP_NOT_EQ => {
-                let literal = self.fetch16()?;
-                let address = self.fetch16()?;
-
-                if literal != self.get_register("acc")? {
-                    self.set_register("ip", address)?;
+            // Jump if the literal compares with acc
+            Instruction::JmpLit(condition, literal, address) => {
+                if condition.holds(literal, self.registers.get(Register::Acc)) {
+                    self.registers.set(Register::Ip, address);
                 }
             }
 
-            // Jump if register not equal
-            instructions::JNE_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
+            // Jump if the register compares with acc
+            Instruction::JmpReg(condition, register, address) => {
+                let register_value = self.registers.get(register);
 
-                if register_value != self.get_register("acc")? {
-                    self.set_register("ip", address)?;
+                if condition.holds(register_value, self.registers.get(Register::Acc)) {
+                    self.registers.set(Register::Ip, address);
                 }
             }
 
-            // Jump if literal equal
-            instructions::JEQ_LIT => {
-                let literal = self.fetch16()?;
-                let address = self.fetch16()?;
+            // Push literal
+            Instruction::PshLit(literal) => {
+                self.push(literal)?;
+            }
 
-                if literal == self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+            // Push register
+            Instruction::PshReg(register) => {
+                let value = self.registers.get(register);
+                self.push(value)?;
             }
 
-            // Jump if register equal
-            instructions::JEQ_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
+            // Pop
+            Instruction::Pop(register) => {
+                let value = self.pop()?;
+                self.registers.set(register, value);
+            }
 
-                if register_value == self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+            // Call literal
+            Instruction::CalLit(address) => {
+                self.push_state()?;
+                self.registers.set(Register::Ip, address);
             }
 
-            // Jump if literal less than
-            instructions::JLT_LIT => {
-                let literal = self.fetch16()?;
-                let address = self.fetch16()?;
+            // Call register
+            Instruction::CalReg(register) => {
+                let address = self.registers.get(register);
+                self.push_state()?;
+                self.registers.set(Register::Ip, address);
+            }
 
-                if literal < self.get_register("acc")? {
-                    self.set_register("ip", address)?;
+            // Return from subroutine
+            Instruction::Ret => {
+                self.pop_state()?;
+            }
+
+            // Software interrupt
+            Instruction::Int(value) => {
+                // RTI would return to the interrupted code instead of after this INT
+                if self.is_in_interrupt_handler {
+                    return Err(VmError::NestedInterrupt { ip: address });
                 }
+                self.handle_interrupt(value & 0xF)?;
             }
 
-            // Jump if register less than
-            instructions::JLT_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
+            // Call host function
+            Instruction::Sys(number) => {
+                self.call_host(number)?;
+                return Ok(self.halted);
+            }
 
-                if register_value < self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+            // Return from interrupt
+            Instruction::RetInt => {
+                self.check_privileged(address, instructions::RET_INT)?;
+                self.pop_state()?;
+                self.is_in_interrupt_handler = false;
+                self.mode = self.interrupted_mode;
             }
 
-            // Jump if literal greater than
-            instructions::JGT_LIT => {
-                let literal = self.fetch16()?;
-                let address = self.fetch16()?;
+            // Load page table and enable the MMU
+            Instruction::Lpt(register) => {
+                self.check_privileged(address, instructions::LPT_REG)?;
+                self.mmu.enable(self.registers.get(register));
+            }
 
-                if literal > self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+            // Switch to user mode
+            Instruction::Usr => {
+                self.check_privileged(address, instructions::USR)?;
+                self.mode = Mode::User;
             }
 
-            // Jump if register greater than
-            instructions::JGT_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
+            // Halt all computation
+            Instruction::Hlt => {
+                self.check_privileged(address, instructions::HLT)?;
+                self.halted = true;
+                return Ok(true);
+            }
+        }
 
-                if register_value > self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+        Ok(false)
+    }
+
+    /// Restores the power-on state of the registers and resets every device.
+    pub fn reset(&mut self) {
+        self.reset_state();
+        self.memory.reset();
+    }
+
+    /// Restores the power-on state of the registers, leaving the devices untouched.
+    fn reset_state(&mut self) {
+        self.registers = RegisterFile::new();
+        self.registers.set(Register::Sp, 0xffff - 1);
+        self.registers.set(Register::Fp, 0xffff - 1);
+        self.registers.set(Register::Im, 0xffff);
+        self.stack_frame_size = 0;
+        self.halted = false;
+        self.exit_status = None;
+        self.is_in_interrupt_handler = false;
+        self.mmu.disable();
+        self.mode = Mode::Supervisor;
+        self.interrupted_mode = Mode::Supervisor;
+        self.cycles = 0;
+        if let Some(cache) = self.instruction_cache.as_mut() {
+            cache.clear();
+        }
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.clear();
+        }
+        self.set_clock_frequency(self.throttle.as_ref().map(|throttle| throttle.frequency));
+    }
+
+    /// Returns the number of cycles elapsed since the last reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Limits the speed of the CPU to the given number of cycles per second, or removes the limit.
+    pub fn set_clock_frequency(&mut self, frequency: Option<u64>) {
+        self.throttle = frequency
+            .filter(|frequency| *frequency > 0)
+            .map(|frequency| Throttle {
+                frequency,
+                start: Instant::now(),
+                start_cycles: self.cycles,
+            });
+    }
+
+    /// Sleeps while the CPU is ahead of its clock frequency.
+    fn throttle(&self) {
+        if let Some(throttle) = &self.throttle {
+            let cycles = u128::from(self.cycles - throttle.start_cycles);
+            let nanos = cycles * 1_000_000_000 / u128::from(throttle.frequency);
+            let target = Duration::from_nanos(nanos as u64);
+            let elapsed = throttle.start.elapsed();
+
+            if target > elapsed + THROTTLE_SLACK {
+                thread::sleep(target - elapsed);
             }
+        }
+    }
 
-            // Jump if literal less than or equal to
-            instructions::JLE_LIT => {
-                let literal = self.fetch16()?;
-                let address = self.fetch16()?;
+    /// Returns true if the CPU executed a halt instruction.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
 
-                if literal <= self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+    /// Enables or disables the cache of decoded instructions, which spares fetching instructions executed again.
+    /// Only instructions in plain memory are cached, and only while the MMU is disabled.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.instruction_cache = if enabled {
+            Some(InstructionCache::new())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the instruction cached at the given address, after dropping the overwritten ones.
+    fn cached_instruction(&mut self, address: u16) -> Option<DecodedInstruction> {
+        if self.mmu.is_enabled() {
+            return None;
+        }
+
+        self.invalidate_code();
+        self.instruction_cache.as_ref()?.get(address)
+    }
+
+    /// Drops the cached instructions and blocks that were overwritten.
+    fn invalidate_code(&mut self) {
+        for page in self.memory.take_invalidated_pages() {
+            if let Some(cache) = self.instruction_cache.as_mut() {
+                cache.invalidate_page(page);
+            }
+            if let Some(cache) = self.block_cache.as_mut() {
+                cache.invalidate_page(page);
             }
+        }
+    }
+
+    /// Caches the instruction decoded at the given address if all its bytes are in plain memory.
+    fn cache_instruction(&mut self, address: u16, decoded: DecodedInstruction) {
+        if self.mmu.is_enabled() {
+            return;
+        }
 
-            // Jump if register less than or equal to
-            instructions::JLE_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
+        if let Some(cache) = self.instruction_cache.as_mut() {
+            let start = address as usize;
+            let end = start + decoded.length as usize - 1;
 
-                if register_value <= self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+            if self.memory.is_plain_memory(start) && self.memory.is_plain_memory(end) {
+                // Writing any byte of the instruction invalidates it
+                self.memory.watch_code_page(start);
+                self.memory.watch_code_page(end);
+                cache.insert(address, decoded);
             }
+        }
+    }
+
+    /// Executes the next instruction.
+    pub fn step(&mut self) -> Result<bool, VmError> {
+        if self.halted {
+            return Err(VmError::Halted);
+        }
 
-            // Jump if literal greater than or equal to
-            instructions::JGE_LIT => {
-                let literal = self.fetch16()?;
-                let address = self.fetch16()?;
+        // Accesses made by the host do not count
+        self.memory.take_wait_cycles();
 
-                if literal >= self.get_register("acc")? {
-                    self.set_register("ip", address)?;
-                }
+        let mut cycles = 0;
+        if self.handle_pending_interrupts()? {
+            // Entering a handler saves the same state as INT
+            cycles += instructions::cycle_cost(instructions::INT);
+        }
+
+        // Faults restart the instruction, so its effects on the registers are undone
+        let registers = self.registers.clone();
+        let stack_frame_size = self.stack_frame_size;
+
+        let address = self.registers.get(Register::Ip);
+        let result = match self.cached_instruction(address) {
+            Some(decoded) => {
+                self.registers.set(Register::Ip, address + decoded.length);
+                cycles += instructions::cycle_cost(decoded.opcode);
+                self.execute_instruction(address, decoded.instruction)
             }
+            None => self.fetch().and_then(|opcode| {
+                cycles += instructions::cycle_cost(opcode);
+                let instruction = self.decode(opcode)?;
+                let length = self.registers.get(Register::Ip) - address;
+                self.cache_instruction(
+                    address,
+                    DecodedInstruction {
+                        opcode,
+                        instruction,
+                        length,
+                    },
+                );
+                self.execute_instruction(address, instruction)
+            }),
+        };
 
-            // Jump if register greater than or equal to
-            instructions::JGE_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.fetch16()?;
-                let register_value = self.registers.get_u16(register)?;
+        // A failed fetch still takes a cycle
+        self.complete_instruction(result, registers, stack_frame_size, cycles.max(1))
+    }
 
-                if register_value >= self.get_register("acc")? {
-                    self.set_register("ip", address)?;
+    /// Raises the fault of a failed instruction, then lets the cycles of the instruction elapse.
+    /// The given registers are the ones from before the instruction.
+    fn complete_instruction(
+        &mut self,
+        result: Result<bool, VmError>,
+        registers: RegisterFile,
+        stack_frame_size: u16,
+        cycles: u64,
+    ) -> Result<bool, VmError> {
+        let halt = match result {
+            Ok(halt) => halt,
+            Err(error) => {
+                if !self.raise_fault(&error, registers, stack_frame_size)? {
+                    return Err(error);
                 }
+                false
             }
+        };
 
-            // Push literal
-            instructions::PSH_LIT => {
-                let literal = self.fetch16()?;
-                self.push(literal)?;
-            }
+        let cycles = cycles + self.memory.take_wait_cycles();
+        self.cycles += cycles;
+        self.memory.tick(cycles);
+        self.throttle();
 
-            // Push register
-            instructions::PSH_REG => {
-                let register = self.fetch_register_index()?;
-                let value = self.registers.get_u16(register)?;
-                self.push(value)?;
-            }
+        Ok(halt)
+    }
 
-            // Pop
-            instructions::POP => {
-                let register = self.fetch_register_index()?;
-                let value = self.pop()?;
-                self.registers.set_u16(register, value)?;
-            }
+    /// Enables or disables the translation of basic blocks into closures, used by `run` and `run_for`.
+    /// Only blocks in plain memory are translated, and only while the MMU is disabled.
+    pub fn set_block_translation(&mut self, enabled: bool) {
+        self.block_cache = if enabled {
+            Some(BlockCache::new())
+        } else {
+            None
+        };
+    }
 
-            // Call literal
-            instructions::CAL_LIT => {
-                let address = self.fetch16()?;
-                self.push_state()?;
-                self.set_register("ip", address)?;
-            }
+    /// Returns the block starting at the given address, translating it if needed.
+    fn translated_block(&mut self, address: u16) -> Option<Rc<Block>> {
+        self.invalidate_code();
+        if let Some(block) = self.block_cache.as_ref()?.get(address) {
+            return Some(block);
+        }
 
-            // Call register
-            instructions::CAL_REG => {
-                let register = self.fetch_register_index()?;
-                let address = self.registers.get_u16(register)?;
-                self.push_state()?;
-                self.set_register("ip", address)?;
-            }
+        let block = Rc::new(block_translator::translate_block(self, address)?);
+        self.memory.watch_code_page(address as usize);
+        self.memory.watch_code_page(block.end);
+        self.block_cache
+            .as_mut()?
+            .insert(address, Rc::clone(&block));
+        Some(block)
+    }
 
-            // Return from subroutine
-            instructions::RET => {
-                self.pop_state()?;
-            }
+    /// Executes the translated block at the instruction pointer, or a single instruction if it cannot be translated.
+    /// Stops after the instruction that requests an interrupt, overwrites code or reaches the cycle limit.
+    /// Returns true if the CPU should halt.
+    fn run_block(&mut self, cycle_limit: u64) -> Result<bool, VmError> {
+        if self.halted || self.mmu.is_enabled() || self.interrupt_requests() != 0 {
+            return self.step();
+        }
 
-            // Halt all computation
-            instructions::HLT => {
-                return Ok(true);
-            }
+        let block = match self.translated_block(self.registers.get(Register::Ip)) {
+            Some(block) => block,
+            None => return self.step(),
+        };
 
-            _ => {
-                // Do nothing
+        // Accesses made by the host do not count
+        self.memory.take_wait_cycles();
+
+        for translated in block.instructions.iter() {
+            let registers = self.registers.clone();
+            let stack_frame_size = self.stack_frame_size;
+
+            self.registers.set(Register::Ip, translated.next);
+            let result = (translated.operation)(self);
+            let halt =
+                self.complete_instruction(result, registers, stack_frame_size, translated.cycles)?;
+
+            if halt
+                || self.registers.get(Register::Ip) != translated.next
+                || self.cycles >= cycle_limit
+                || self.memory.has_invalidated_pages()
+                || self.interrupt_requests() != 0
+            {
+                return Ok(halt);
             }
         }
 
         Ok(false)
     }
 
-    /// Executes the next instruction.
-    pub fn step(&mut self) -> Result<bool, String> {
-        let instruction = self.fetch()?;
-        self.execute(instruction)
+    /// Executes the next block if block translation is enabled, or the next instruction otherwise.
+    fn run_next(&mut self, cycle_limit: u64) -> Result<bool, VmError> {
+        if self.block_cache.is_some() {
+            self.run_block(cycle_limit)
+        } else {
+            self.step()
+        }
     }
 
     /// Runs the CPU
-    pub fn run(&mut self) -> Result<(), String> {
+    pub fn run(&mut self) -> Result<(), VmError> {
         loop {
-            let halt = self.step()?;
+            let halt = self.run_next(u64::MAX)?;
             if halt {
                 break;
             }
@@ -582,6 +1181,24 @@ impl CPU {
         Ok(())
     }
 
+    /// Runs the CPU until it halts or the given number of cycles elapse.
+    /// Returns the number of cycles elapsed, which may exceed the given number by the cost of the last instruction.
+    pub fn run_for(&mut self, cycles: u64) -> Result<u64, VmError> {
+        let mut elapsed = 0;
+        while elapsed < cycles && !self.halted {
+            let start = self.cycles;
+            self.run_next(start.saturating_add(cycles - elapsed))?;
+
+            // A reset, e.g. from a host call, restarts the count from 0
+            elapsed += if self.cycles >= start {
+                self.cycles - start
+            } else {
+                self.cycles
+            };
+        }
+        Ok(elapsed)
+    }
+
     /// Prints the memory at the given address.
     pub fn view_memory_at(&mut self, address: usize, num_bytes: Option<usize>) {
         let num_bytes = num_bytes.unwrap_or(8);
@@ -598,22 +1215,753 @@ impl CPU {
 
 impl fmt::Debug for CPU {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("CPU")
-            .field("ip", &format!("{:#06X}", self.get_register("ip").unwrap()))
-            .field(
-                "acc",
-                &format!("{:#06X}", self.get_register("acc").unwrap()),
+        let mut debug_struct = f.debug_struct("CPU");
+        for register in Register::ALL.iter() {
+            debug_struct.field(
+                register.name(),
+                &format!("{:#06X}", self.registers.get(*register)),
+            );
+        }
+        debug_struct.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        device::Device,
+        memory::Memory,
+        mmu::{PageTableEntry, PAGE_EXECUTE, PAGE_READ, PAGE_USER, PAGE_WRITE},
+        timer_device::TimerDevice,
+    };
+
+    fn cpu_with_program(program: &[u8]) -> CPU {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0, 0xffff, false)
+            .unwrap();
+        memory_mapper.load_image(program, 0, "program").unwrap();
+
+        CPU::new(memory_mapper).unwrap()
+    }
+
+    fn set_vector(cpu: &mut CPU, index: usize, handler: u16) {
+        let address = DEFAULT_INTERRUPT_VECTOR_ADDRESS as usize + index * 2;
+        cpu.memory.set_u16(address, handler).unwrap();
+    }
+
+    #[test]
+    fn software_interrupt_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x12, 0x34, 2, // mov $1234, r1
+            instructions::INT, 0x00, 0x03,            // int $3
+            instructions::HLT,
+        ]);
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_LIT_REG, 0x00, 0x01, 2, // mov $1, r1
+            instructions::MOV_LIT_REG, 0x00, 0x42, 1, // mov $42, acc
+            instructions::RET_INT,
+        ];
+        cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+        set_vector(&mut cpu, 3, 0x2000);
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("acc"), Ok(0x42));
+        assert_eq!(cpu.get_register("r1"), Ok(0x1234));
+        assert_eq!(cpu.get_register("ip"), Ok(8));
+        assert_eq!(cpu.get_register("sp"), Ok(0xffff - 1));
+    }
+
+    #[test]
+    fn nested_software_interrupt_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INT, 0x00, 0x03, // int $3
+            instructions::HLT,
+        ]);
+        #[rustfmt::skip]
+        let handler = [
+            instructions::INT, 0x00, 0x04, // int $4
+            instructions::RET_INT,
+        ];
+        cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+        cpu.memory.set_u8(0x2100, instructions::RET_INT).unwrap();
+        set_vector(&mut cpu, 3, 0x2000);
+        set_vector(&mut cpu, 4, 0x2100);
+
+        assert_eq!(cpu.run(), Err(VmError::NestedInterrupt { ip: 0x2000 }));
+    }
+
+    #[test]
+    fn masked_interrupt_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INT, 0x00, 0x01, // int $1
+            instructions::HLT,
+        ]);
+        set_vector(&mut cpu, 1, 0x2000);
+        cpu.set_register("im", !(1 << 1)).unwrap();
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("ip"), Ok(4));
+    }
+
+    #[test]
+    fn hardware_interrupt_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::HLT,
+        ]);
+        cpu.memory.set_u8(0x2000, instructions::INC_REG).unwrap();
+        cpu.memory.set_u8(0x2001, 1).unwrap();
+        cpu.memory.set_u8(0x2002, instructions::RET_INT).unwrap();
+        set_vector(&mut cpu, 5, 0x2000);
+
+        let irq_line = cpu.irq_line();
+        cpu.step().unwrap();
+        irq_line.raise(5);
+        cpu.run().unwrap();
+
+        assert_eq!(irq_line.pending(), 0);
+        assert_eq!(cpu.get_register("r1"), Ok(2));
+        assert_eq!(cpu.get_register("acc"), Ok(1));
+    }
+
+    #[test]
+    fn illegal_opcode_test() {
+        let mut cpu = cpu_with_program(&[instructions::INC_REG, 2, 0x00]);
+
+        assert_eq!(cpu.step(), Ok(false));
+        assert_eq!(
+            cpu.step(),
+            Err(VmError::IllegalOpcode {
+                ip: 2,
+                opcode: 0x00
+            })
+        );
+    }
+
+    #[test]
+    fn halted_test() {
+        let mut cpu = cpu_with_program(&[instructions::HLT]);
+
+        assert_eq!(cpu.run(), Ok(()));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.step(), Err(VmError::Halted));
+    }
+
+    /// Maps every page to itself for user mode, except the given ones.
+    fn identity_page_table(cpu: &mut CPU, exceptions: &[(u8, u8)]) {
+        for page in 0..=0xFF {
+            let flags = exceptions
+                .iter()
+                .find(|(exception, _)| *exception == page)
+                .map_or(
+                    PAGE_READ | PAGE_WRITE | PAGE_EXECUTE | PAGE_USER,
+                    |(_, flags)| *flags,
+                );
+
+            cpu.memory
+                .set_u16(
+                    0x0400 + usize::from(page) * 2,
+                    PageTableEntry::new(page, flags).0,
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn page_fault_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x04, 0x00, 2,          // mov $0400, r1
+            instructions::LPT_REG, 2,                          // lpt r1
+            instructions::USR,                                 // usr
+            instructions::MOV_LIT_MEM, 0x12, 0x34, 0x30, 0x00, // mov $1234, &3000
+            instructions::MOV_MEM_REG, 0x30, 0x00, 1,          // mov &3000, acc
+            instructions::INT, 0x00, 0x01,                     // int $1
+        ]);
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_REG_MEM, 2, 0x21, 0x00,          // mov r1, &2100
+            instructions::MOV_REG_MEM, 3, 0x21, 0x02,          // mov r2, &2102
+            instructions::MOV_LIT_MEM, 0x30, 0x0B, 0x04, 0x60, // mov $300b, &0460
+            instructions::RET_INT,
+        ];
+        cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+        cpu.memory.set_u8(0x2200, instructions::HLT).unwrap();
+        set_vector(&mut cpu, PAGE_FAULT_VECTOR as usize, 0x2000);
+        set_vector(&mut cpu, 1, 0x2200);
+        identity_page_table(
+            &mut cpu,
+            &[
+                (0x20, PAGE_READ | PAGE_WRITE | PAGE_EXECUTE),
+                (0x21, PAGE_READ | PAGE_WRITE),
+                (0x22, PAGE_EXECUTE),
+                (0x30, PAGE_READ | PAGE_USER),
+            ],
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("acc"), Ok(0x1234));
+        assert_eq!(cpu.memory.get_u16(0x2100), Ok(0x3000));
+        assert_eq!(cpu.memory.get_u16(0x2102), Ok(Access::Write.code()));
+        assert_eq!(cpu.mode(), Mode::Supervisor);
+        assert_eq!(cpu.mmu().page_table_address(), Some(0x0400));
+    }
+
+    #[test]
+    fn privileged_instruction_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::USR,
+            instructions::HLT,
+        ]);
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_REG_REG, 2, 1, // mov r1, acc
+            instructions::HLT,
+        ];
+        cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+        set_vector(&mut cpu, GENERAL_PROTECTION_FAULT_VECTOR as usize, 0x2000);
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("acc"), Ok(0x0001));
+        assert_eq!(cpu.mode(), Mode::Supervisor);
+    }
+
+    #[test]
+    fn privileged_register_test() {
+        #[rustfmt::skip]
+        let program = [
+            instructions::USR,
+            instructions::MOV_LIT_REG, 0x00, 0x00, 0x0C, // mov $0000, im
+        ];
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_REG_REG, 3, 1, // mov r2, acc
+            instructions::HLT,
+        ];
+
+        for translated in [false, true] {
+            let mut cpu = cpu_with_program(&program);
+            cpu.set_block_translation(translated);
+            cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+            set_vector(&mut cpu, GENERAL_PROTECTION_FAULT_VECTOR as usize, 0x2000);
+
+            cpu.run().unwrap();
+
+            assert_eq!(
+                cpu.get_register("acc"),
+                Ok(instructions::MOV_LIT_REG.into())
+            );
+            assert_eq!(cpu.get_register("r1"), Ok(0x0001));
+            assert_eq!(cpu.get_register("im"), Ok(0xffff));
+        }
+    }
+
+    #[test]
+    fn double_fault_test() {
+        let mut cpu = cpu_with_program(&[instructions::USR, instructions::HLT]);
+        cpu.memory.set_u8(0x2000, instructions::USR).unwrap();
+        cpu.memory.set_u8(0x2001, instructions::HLT).unwrap();
+        set_vector(&mut cpu, GENERAL_PROTECTION_FAULT_VECTOR as usize, 0x2000);
+
+        assert_eq!(
+            cpu.run(),
+            Err(VmError::PrivilegedInstruction {
+                ip: 0x2001,
+                opcode: instructions::HLT
+            })
+        );
+    }
+
+    #[test]
+    fn interrupt_stack_protection_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x04, 0x00, 2,  // mov $0400, r1
+            instructions::LPT_REG, 2,                  // lpt r1
+            instructions::USR,                         // usr
+            instructions::MOV_LIT_REG, 0x04, 0xFE, 10, // mov $04fe, sp
+            instructions::INT, 0x00, 0x01,             // int $1
+        ]);
+        cpu.memory.set_u8(0x2000, instructions::HLT).unwrap();
+        set_vector(&mut cpu, 1, 0x2000);
+        set_vector(&mut cpu, PAGE_FAULT_VECTOR as usize, 0x2000);
+        identity_page_table(&mut cpu, &[(0x04, PAGE_READ | PAGE_WRITE)]);
+        let page_table_entry = cpu.memory.get_u16(0x04FE).unwrap();
+
+        assert_eq!(
+            cpu.run(),
+            Err(VmError::DoubleFault {
+                vector: 1,
+                sp: 0x04FE
+            })
+        );
+        assert_eq!(cpu.memory.get_u16(0x04FE), Ok(page_table_entry));
+        assert_eq!(cpu.mode(), Mode::User);
+        assert_eq!(cpu.get_register("sp"), Ok(0x04FE));
+    }
+
+    #[test]
+    fn host_call_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x00, 0x02, 2, // mov $2, r1
+            instructions::MOV_LIT_REG, 0x00, 0x03, 3, // mov $3, r2
+            instructions::SYS_LIT, 0x00, 0x10,        // sys $10
+            instructions::SYS_LIT, 0x00, 0x11,        // sys $11
+        ]);
+        cpu.register_host_call(0x10, |cpu| {
+            let product = cpu.registers().get(Register::R1) * cpu.registers().get(Register::R2);
+            cpu.registers_mut().set(Register::Acc, product);
+            Ok(())
+        });
+
+        assert_eq!(
+            cpu.run(),
+            Err(VmError::UnknownHostCall {
+                ip: 0x000B,
+                number: 0x11
+            })
+        );
+        assert_eq!(cpu.get_register("acc"), Ok(6));
+    }
+
+    #[test]
+    fn invalid_register_index_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x00, 0x01, 0x0D, // mov $1, <invalid>
+        ]);
+
+        assert_eq!(
+            cpu.step(),
+            Err(VmError::InvalidRegister { ip: 3, index: 13 })
+        );
+        assert_eq!(cpu.registers().get(Register::Ip), 4);
+    }
+
+    #[test]
+    fn new_keeps_device_state_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(Box::new(TimerDevice::new()), 0x4000, 0x4007, true)
+            .unwrap();
+        memory_mapper.set_u16(0x4000, 100).unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        assert_eq!(cpu.memory.get_u16(0x4002), Ok(100));
+
+        cpu.reset();
+        assert_eq!(cpu.memory.get_u16(0x4002), Ok(0));
+    }
+
+    #[test]
+    fn cycle_count_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::MOV_LIT_REG, 0x12, 0x34, 3,         // mov $1234, r2
+            instructions::MOV_REG_MEM, 3, 0x40, 0x00,         // mov r2, &4000
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00, // jne $ffff, &0000
+        ]);
+        let handle = cpu
+            .memory
+            .map_memory(Memory::new(0x100), 0x4000, 0x40FF, true)
+            .unwrap();
+        cpu.memory.set_wait_states(handle, 5).unwrap();
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles(), 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles(), 3);
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles(), 11);
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles(), 14);
+
+        // The last instruction started before the budget ran out
+        assert_eq!(cpu.run_for(16), Ok(17));
+        assert_eq!(cpu.get_register("r1"), Ok(3));
+        assert_eq!(cpu.cycles(), 31);
+
+        cpu.reset();
+        assert_eq!(cpu.cycles(), 0);
+    }
+
+    #[test]
+    fn run_for_reset_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::SYS_LIT, 0x00, 0x01,                // sys $1
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00, // jne $ffff, &0000
+        ]);
+        cpu.register_host_call(1, |cpu| {
+            cpu.reset();
+            Ok(())
+        });
+        // The reset brings the count below the one run_for starts from
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+
+        let elapsed = cpu.run_for(20).unwrap();
+        assert!(elapsed >= 20);
+        assert!(cpu.cycles() < 20);
+    }
+
+    #[test]
+    fn run_for_halt_test() {
+        let mut cpu = cpu_with_program(&[instructions::INC_REG, 2, instructions::HLT]);
+
+        assert_eq!(cpu.run_for(100), Ok(2));
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    fn throttle_test() {
+        let mut cpu = cpu_with_program(&[instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00]);
+        cpu.set_clock_frequency(Some(10_000));
+
+        let start = Instant::now();
+        cpu.run_for(300).unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(25));
+    }
+
+    /// Requests an interrupt after a number of cycles, until its status register is read.
+    struct CountdownDevice {
+        remaining: u64,
+    }
+
+    impl Device for CountdownDevice {
+        fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+            Ok(self.get_u8(address)? as u16)
+        }
+
+        fn get_u8(&mut self, _address: usize) -> Result<u8, VmError> {
+            let status = (self.remaining == 0) as u8;
+            self.remaining = u64::MAX;
+            Ok(status)
+        }
+
+        fn set_u16(&mut self, _address: usize, _value: u16) -> Result<(), VmError> {
+            Ok(())
+        }
+
+        fn set_u8(&mut self, _address: usize, _value: u8) -> Result<(), VmError> {
+            Ok(())
+        }
+
+        fn reset(&mut self) {
+            self.remaining = 3;
+        }
+
+        fn tick(&mut self, cycles: u64) {
+            self.remaining = self.remaining.saturating_sub(cycles);
+        }
+
+        fn irq_pending(&self) -> Option<u16> {
+            if self.remaining == 0 {
+                Some(2)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn device_interrupt_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::HLT,
+        ]);
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_MEM_REG, 0x40, 0x00, 1, // mov &4000, acc
+            instructions::RET_INT,
+        ];
+        cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+        cpu.memory
+            .map(
+                Box::new(CountdownDevice { remaining: 0 }),
+                0x4000,
+                0x4000,
+                true,
+            )
+            .unwrap();
+        set_vector(&mut cpu, 2, 0x2000);
+        cpu.reset();
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("r1"), Ok(4));
+        assert_eq!(cpu.get_register("acc"), Ok(0x0100));
+        assert_eq!(cpu.memory.device_name(0x4000), Some("CountdownDevice"));
+    }
+
+    /// Steps the CPU until it halts, fails or reaches the given number of steps.
+    fn run_steps(cpu: &mut CPU, max_steps: usize) -> Result<(), VmError> {
+        for _ in 0..max_steps {
+            if cpu.step()? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the program on the interpreter, with the instruction cache and with block translation,
+    /// and checks that every run ends in the same state. Returns the run with block translation.
+    fn assert_same_across_engines(program: &[u8], setup: fn(&mut CPU)) -> CPU {
+        let engines: [fn(&mut CPU); 3] = [
+            |_| {},
+            |cpu| cpu.set_instruction_cache(true),
+            |cpu| cpu.set_block_translation(true),
+        ];
+        let mut runs = engines.iter().map(|engine| {
+            let mut cpu = cpu_with_program(program);
+            engine(&mut cpu);
+            setup(&mut cpu);
+            let result = cpu.run_for(5000);
+            (cpu, result)
+        });
+
+        let (mut expected, expected_result) = runs.next().unwrap();
+        let mut last = None;
+        for (mut cpu, result) in runs {
+            assert_eq!(result, expected_result);
+            assert_eq!(cpu.registers(), expected.registers());
+            assert_eq!(cpu.cycles(), expected.cycles());
+            assert_eq!(cpu.is_halted(), expected.is_halted());
+            for address in 0..0x10000 {
+                assert_eq!(
+                    cpu.memory.get_u8(address),
+                    expected.memory.get_u8(address),
+                    "memory differs at {:#06X}",
+                    address
+                );
+            }
+            last = Some(cpu);
+        }
+
+        last.unwrap()
+    }
+
+    #[test]
+    fn cached_loop_test() {
+        #[rustfmt::skip]
+        let cpu = assert_same_across_engines(&[
+            instructions::MOV_LIT_REG, 0x00, 0x03, 3,         // mov $0003, r2
+            instructions::MUL_REG_REG, 3, 4,                  // mul r2, r3
+            instructions::MOV_REG_REG, 1, 4,                  // mov acc, r3
+            instructions::ADD_LIT_REG, 0x00, 0x01, 4,         // add $0001, r3
+            instructions::MOV_REG_REG, 1, 4,                  // mov acc, r3
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::MOV_REG_REG, 2, 1,                  // mov r1, acc
+            instructions::JMP_NOT_EQ, 0x00, 0x05, 0x00, 0x04, // jne $0005, &0004
+            instructions::HLT,
+        ], |_| {});
+
+        assert_eq!(cpu.registers().get(Register::R3), 121);
+    }
+
+    #[test]
+    fn cached_call_test() {
+        #[rustfmt::skip]
+        let mut program = vec![
+            instructions::PSH_LIT, 0x00, 0x03,        // psh $0003
+            instructions::PSH_LIT, 0x00, 0x01,        // psh $0001
+            instructions::CAL_LIT, 0x00, 0x20,        // cal &0020
+            instructions::MOV_REG_MEM, 1, 0x30, 0x00, // mov acc, &3000
+            instructions::HLT,
+        ];
+        program.resize(0x20, 0);
+        #[rustfmt::skip]
+        program.extend_from_slice(&[
+            instructions::MOV_LIT_REG, 0x00, 0x07, 3, // mov $0007, r2
+            instructions::MUL_REG_REG, 3, 3,          // mul r2, r2
+            instructions::ADD_LIT_REG, 0x00, 0x01, 1, // add $0001, acc
+            instructions::RET,
+        ]);
+
+        let mut cpu = assert_same_across_engines(&program, |_| {});
+
+        assert_eq!(cpu.memory.get_u16(0x3000), Ok(50));
+    }
+
+    #[test]
+    fn cached_interrupt_test() {
+        #[rustfmt::skip]
+        let mut cpu = assert_same_across_engines(&[
+            instructions::INT, 0x00, 0x03, // int $3
+            instructions::INC_REG, 2,      // inc r1
+            instructions::USR,             // usr
+            instructions::HLT,             // hlt
+        ], |cpu| {
+            #[rustfmt::skip]
+            let handler = [
+                instructions::MOV_LIT_MEM, 0x00, 0x01, 0x30, 0x00, // mov $0001, &3000
+                instructions::RET_INT,
+            ];
+            #[rustfmt::skip]
+            let fault_handler = [
+                instructions::INC_REG, 5, // inc r4
+                instructions::HLT,
+            ];
+            cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+            cpu.memory
+                .load_image(&fault_handler, 0x2100, "fault_handler")
+                .unwrap();
+            set_vector(cpu, 3, 0x2000);
+            set_vector(cpu, GENERAL_PROTECTION_FAULT_VECTOR as usize, 0x2100);
+        });
+
+        // The HLT executed in user mode faults
+        assert_eq!(cpu.memory.get_u16(0x3000), Ok(1));
+        assert_eq!(cpu.registers().get(Register::R1), 6);
+        assert_eq!(cpu.registers().get(Register::R4), 1);
+        assert_eq!(
+            cpu.registers().get(Register::R2),
+            u16::from(instructions::HLT)
+        );
+    }
+
+    #[test]
+    fn self_modifying_code_test() {
+        #[rustfmt::skip]
+        let cpu = assert_same_across_engines(&[
+            instructions::MOV_LIT_REG, 0x00, 0x00, 2,         // mov $0000, r1
+            instructions::INC_REG, 1,                         // inc acc
+            instructions::MOV_REG_MEM, 1, 0x00, 0x01,         // mov acc, &0001
+            instructions::JMP_NOT_EQ, 0x00, 0x05, 0x00, 0x00, // jne $0005, &0000
+            instructions::HLT,
+        ], |_| {});
+
+        // Each iteration loads the literal written by the previous one
+        assert_eq!(cpu.registers().get(Register::R1), 4);
+    }
+
+    #[test]
+    fn cache_remap_test() {
+        #[rustfmt::skip]
+        let program = [
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00, // jne $ffff, &0000
+        ];
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_instruction_cache(true);
+        run_steps(&mut cpu, 4).unwrap();
+        assert_eq!(cpu.registers().get(Register::R1), 2);
+
+        // Neither the host writing the code nor a new mapping over it leave stale instructions
+        cpu.memory.set_u8(0x01, 3).unwrap();
+        run_steps(&mut cpu, 2).unwrap();
+        assert_eq!(cpu.registers().get(Register::R2), 1);
+
+        cpu.memory
+            .map_memory(
+                Memory::from_bytes(&[instructions::INC_REG, 4]),
+                0x0000,
+                0x0001,
+                true,
+            )
+            .unwrap();
+        run_steps(&mut cpu, 2).unwrap();
+        assert_eq!(cpu.registers().get(Register::R3), 1);
+    }
+
+    #[test]
+    fn block_self_modifying_test() {
+        #[rustfmt::skip]
+        let cpu = assert_same_across_engines(&[
+            instructions::MOV_LIT_MEM, 0x00, 0x05, 0x00, 0x06, // mov $0005, &0006
+            instructions::MOV_LIT_REG, 0x00, 0x00, 2,          // mov $0000, r1
+            instructions::HLT,
+        ], |_| {});
+
+        // The write patches the next instruction of the same block
+        assert_eq!(cpu.registers().get(Register::R1), 5);
+    }
+
+    #[test]
+    fn block_device_interrupt_test() {
+        #[rustfmt::skip]
+        let cpu = assert_same_across_engines(&[
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::INC_REG, 2, // inc r1
+            instructions::HLT,
+        ], |cpu| {
+            #[rustfmt::skip]
+            let handler = [
+                instructions::MOV_MEM_REG, 0x40, 0x00, 1, // mov &4000, acc
+                instructions::RET_INT,
+            ];
+            cpu.memory.load_image(&handler, 0x2000, "handler").unwrap();
+            cpu.memory
+                .map(
+                    Box::new(CountdownDevice { remaining: 0 }),
+                    0x4000,
+                    0x4000,
+                    true,
+                )
+                .unwrap();
+            set_vector(cpu, 2, 0x2000);
+            cpu.reset();
+        });
+
+        // The interrupt is serviced in the middle of the block
+        assert_eq!(cpu.registers().get(Register::R1), 4);
+        assert_eq!(cpu.registers().get(Register::Acc), 0x0100);
+    }
+
+    #[test]
+    fn block_remap_test() {
+        #[rustfmt::skip]
+        let program = [
+            instructions::INC_REG, 2,                         // inc r1
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00, // jne $ffff, &0000
+        ];
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_block_translation(true);
+        assert_eq!(cpu.run_for(8), Ok(8));
+        assert_eq!(cpu.registers().get(Register::R1), 2);
+
+        // Neither the host writing the code nor a new mapping over it leave stale blocks
+        cpu.memory.set_u8(0x01, 3).unwrap();
+        cpu.run_for(4).unwrap();
+        assert_eq!(cpu.registers().get(Register::R2), 1);
+
+        cpu.memory
+            .map_memory(
+                Memory::from_bytes(&[instructions::INC_REG, 4]),
+                0x0000,
+                0x0001,
+                true,
             )
-            .field("r1", &format!("{:#06X}", self.get_register("r1").unwrap()))
-            .field("r2", &format!("{:#06X}", self.get_register("r2").unwrap()))
-            .field("r3", &format!("{:#06X}", self.get_register("r3").unwrap()))
-            .field("r4", &format!("{:#06X}", self.get_register("r4").unwrap()))
-            .field("r5", &format!("{:#06X}", self.get_register("r5").unwrap()))
-            .field("r6", &format!("{:#06X}", self.get_register("r6").unwrap()))
-            .field("r7", &format!("{:#06X}", self.get_register("r7").unwrap()))
-            .field("r8", &format!("{:#06X}", self.get_register("r8").unwrap()))
-            .field("sp", &format!("{:#06X}", self.get_register("sp").unwrap()))
-            .field("fp", &format!("{:#06X}", self.get_register("fp").unwrap()))
-            .finish()
+            .unwrap();
+        cpu.run_for(1).unwrap();
+        assert_eq!(cpu.registers().get(Register::R3), 1);
     }
 }
@@ -1,6 +1,142 @@
+use crate::virtual_machine::{error::VmError, memory_mapper::Bus};
+use std::{cell::RefCell, rc::Rc};
+
 pub trait Device {
-    fn get_u16(&self, address: usize) -> Result<u16, String>;
-    fn get_u8(&self, address: usize) -> Result<u8, String>;
-    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), String>;
-    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), String>;
-}
\ No newline at end of file
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError>;
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError>;
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError>;
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError>;
+
+    /// Fails with the error a u8 write at the given address would return, without writing.
+    ///
+    /// Devices with read-only addresses must override it, as `Memory`, `RomDevice` and
+    /// `RtcDevice` do. The other devices only reject writes past their registers, or
+    /// values they cannot take, like the screen with bytes that are not characters.
+    fn check_write(&self, _address: usize) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    /// Restores the state of the device after a reset of the machine.
+    fn reset(&mut self) {}
+
+    /// Advances the device by the given number of CPU cycles.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Returns the index of the interrupt vector the device is requesting, if any.
+    /// The request stays active for as long as this returns it.
+    fn irq_pending(&self) -> Option<u16> {
+        None
+    }
+
+    /// Returns whether the device needs to access the memory, e.g. to complete a transfer.
+    fn wants_bus(&self) -> bool {
+        false
+    }
+
+    /// Lets the device access the memory after a tick in which `wants_bus` returned true.
+    /// Accesses to the registers of the device itself fail with `VmError::BusConflict`.
+    fn master_bus(&mut self, _bus: &mut Bus) {}
+
+    /// Returns a short name for the device, used for debugging.
+    fn name(&self) -> &str {
+        let device_type = self.device_type();
+        let path = device_type.split('<').next().unwrap_or(device_type);
+        path.rsplit("::").next().unwrap_or(path)
+    }
+
+    /// Returns the type name of the device.
+    fn device_type(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// A device made of 16-bit registers at even device addresses.
+/// Its `Device` accesses forward to the `*_register_*` methods, which split the byte accesses.
+/// Devices with byte-sized registers, like the keyboard, or byte-addressed memory, like the
+/// framebuffer, keep their own `Device` accesses.
+pub trait WordRegisters {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError>;
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError>;
+
+    fn get_register_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        if address & 1 != 0 {
+            return Err(VmError::MisalignedRegister { address });
+        }
+
+        self.get_register(address)
+    }
+
+    /// Reads the high byte of a register at its even address and the low byte at the odd one.
+    fn get_register_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        let [high, low] = self.get_register(address & !1)?.to_be_bytes();
+        Ok(if address & 1 == 0 { high } else { low })
+    }
+
+    fn set_register_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        if address & 1 != 0 {
+            return Err(VmError::MisalignedRegister { address });
+        }
+
+        self.set_register(address, value)
+    }
+
+    /// Writes one byte of a register, keeping the other byte.
+    fn set_register_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        let register = address & !1;
+        let [high, low] = self.get_register(register)?.to_be_bytes();
+
+        let value = if address & 1 == 0 {
+            u16::from_be_bytes([value, low])
+        } else {
+            u16::from_be_bytes([high, value])
+        };
+        self.set_register(register, value)
+    }
+}
+
+/// Lets the host keep a handle on a device after mapping a clone of it.
+impl<D: Device> Device for Rc<RefCell<D>> {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.borrow_mut().get_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.borrow_mut().get_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.borrow_mut().set_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.borrow_mut().set_u8(address, value)
+    }
+
+    fn check_write(&self, address: usize) -> Result<(), VmError> {
+        self.borrow().check_write(address)
+    }
+
+    fn reset(&mut self) {
+        self.borrow_mut().reset()
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.borrow_mut().tick(cycles)
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        self.borrow().irq_pending()
+    }
+
+    fn wants_bus(&self) -> bool {
+        self.borrow().wants_bus()
+    }
+
+    fn master_bus(&mut self, bus: &mut Bus) {
+        self.borrow_mut().master_bus(bus)
+    }
+
+    fn device_type(&self) -> &'static str {
+        self.borrow().device_type()
+    }
+}
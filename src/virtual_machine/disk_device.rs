@@ -0,0 +1,393 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+    memory_mapper::{Bus, MemoryMapper},
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Size in bytes of a sector.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Sector register: the sector to transfer.
+pub const DISK_SECTOR: usize = 0x00;
+/// Address register: the memory address the sector is transferred from or to.
+pub const DISK_ADDRESS: usize = 0x02;
+/// Command register: writing one of the `COMMAND_*` values starts a transfer.
+pub const DISK_COMMAND: usize = 0x04;
+/// Status register, made of the `STATUS_*` flags. Writing clears the done and error flags.
+pub const DISK_STATUS: usize = 0x06;
+/// Control register, made of the `CONTROL_*` flags.
+pub const DISK_CONTROL: usize = 0x08;
+
+/// Command that copies the sector to the memory.
+pub const COMMAND_READ: u16 = 0x01;
+/// Command that copies the memory to the sector.
+pub const COMMAND_WRITE: u16 = 0x02;
+
+/// Status bit set while a transfer is in progress.
+pub const STATUS_BUSY: u16 = 0x01;
+/// Status bit set when a transfer completes.
+pub const STATUS_DONE: u16 = 0x02;
+/// Status bit set when a transfer fails, along with the done bit.
+pub const STATUS_ERROR: u16 = 0x04;
+
+/// Control bit that enables the completion interrupt.
+pub const CONTROL_INTERRUPT_ENABLE: u16 = 0x01;
+
+/// A disk made of 512-byte sectors, stored in a host image.
+///
+/// Transfers go through the memory mapper once the latency of the disk has
+/// elapsed, so they can target any mapped device.
+pub struct DiskDevice<S: Read + Write + Seek = File> {
+    storage: S,
+    sector_count: u16,
+    sector: u16,
+    address: u16,
+    command: u16,
+    status: u16,
+    control: u16,
+    latency: u64,
+    remaining_cycles: u64,
+    interrupt_vector: Option<u16>,
+}
+
+impl DiskDevice<File> {
+    /// Opens the image file at the given path. Its size is rounded down to a whole number of sectors.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<DiskDevice<File>, io::Error> {
+        DiskDevice::new(OpenOptions::new().read(true).write(true).open(path)?)
+    }
+}
+
+impl<S: Read + Write + Seek> DiskDevice<S> {
+    pub fn new(mut storage: S) -> Result<DiskDevice<S>, io::Error> {
+        let size = storage.seek(SeekFrom::End(0))?;
+        let sector_count = (size / SECTOR_SIZE as u64).min(u64::from(u16::MAX)) as u16;
+
+        Ok(DiskDevice {
+            storage,
+            sector_count,
+            sector: 0,
+            address: 0,
+            command: 0,
+            status: 0,
+            control: 0,
+            latency: 0,
+            remaining_cycles: 0,
+            interrupt_vector: None,
+        })
+    }
+
+    /// Sets the number of cycles a transfer takes.
+    pub fn set_latency(&mut self, latency: u64) {
+        self.latency = latency;
+    }
+
+    /// Sets the interrupt vector requested when a transfer completes.
+    pub fn set_interrupt_vector(&mut self, interrupt_vector: Option<u16>) {
+        self.interrupt_vector = interrupt_vector;
+    }
+
+    pub fn sector_count(&self) -> u16 {
+        self.sector_count
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Loads the first sector at the given address, as a segment named "boot".
+    pub fn boot(&mut self, memory: &mut MemoryMapper, address: usize) -> Result<(), VmError> {
+        let sector = self.read_sector(0).map_err(|err| VmError::Device {
+            address,
+            message: err.to_string(),
+        })?;
+
+        memory.load_image(&sector, address, "boot")
+    }
+
+    fn read_sector(&mut self, sector: u16) -> Result<[u8; SECTOR_SIZE], io::Error> {
+        self.check_sector(sector)?;
+
+        let mut bytes = [0; SECTOR_SIZE];
+        self.storage
+            .seek(SeekFrom::Start(u64::from(sector) * SECTOR_SIZE as u64))?;
+        self.storage.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_sector(&mut self, sector: u16, bytes: &[u8; SECTOR_SIZE]) -> Result<(), io::Error> {
+        self.check_sector(sector)?;
+
+        self.storage
+            .seek(SeekFrom::Start(u64::from(sector) * SECTOR_SIZE as u64))?;
+        self.storage.write_all(bytes)?;
+        self.storage.flush()
+    }
+
+    fn check_sector(&self, sector: u16) -> Result<(), io::Error> {
+        if sector < self.sector_count {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sector {} is past the end of the disk", sector),
+            ))
+        }
+    }
+
+    /// Runs the current command. Returns false if it failed.
+    fn transfer(&mut self, bus: &mut Bus) -> bool {
+        let address = usize::from(self.address);
+
+        match self.command {
+            COMMAND_READ => match self.read_sector(self.sector) {
+                Ok(bytes) => bytes
+                    .iter()
+                    .enumerate()
+                    .all(|(offset, byte)| bus.set_u8(address + offset, *byte).is_ok()),
+                Err(_) => false,
+            },
+            COMMAND_WRITE => {
+                let mut bytes = [0; SECTOR_SIZE];
+                for (offset, byte) in bytes.iter_mut().enumerate() {
+                    match bus.get_u8(address + offset) {
+                        Ok(value) => *byte = value,
+                        Err(_) => return false,
+                    }
+                }
+
+                self.write_sector(self.sector, &bytes).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<S: Read + Write + Seek> WordRegisters for DiskDevice<S> {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            DISK_SECTOR => Ok(self.sector),
+            DISK_ADDRESS => Ok(self.address),
+            DISK_COMMAND => Ok(self.command),
+            DISK_STATUS => Ok(self.status),
+            DISK_CONTROL => Ok(self.control),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        // The registers cannot change during a transfer
+        if self.status & STATUS_BUSY != 0 && address != DISK_CONTROL {
+            return match address {
+                DISK_SECTOR | DISK_ADDRESS | DISK_COMMAND | DISK_STATUS => Ok(()),
+                _ => Err(VmError::OutOfBounds { address }),
+            };
+        }
+
+        match address {
+            DISK_SECTOR => self.sector = value,
+            DISK_ADDRESS => self.address = value,
+            DISK_COMMAND => {
+                self.command = value;
+                self.status = STATUS_BUSY;
+                self.remaining_cycles = self.latency;
+            }
+            DISK_STATUS => self.status = 0,
+            DISK_CONTROL => self.control = value,
+            _ => return Err(VmError::OutOfBounds { address }),
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + Seek> Device for DiskDevice<S> {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        // Only writing the low byte of the command starts a transfer
+        if address == DISK_COMMAND {
+            if self.status & STATUS_BUSY == 0 {
+                let [_, low] = self.command.to_be_bytes();
+                self.command = u16::from_be_bytes([value, low]);
+            }
+            return Ok(());
+        }
+
+        self.set_register_u8(address, value)
+    }
+
+    fn reset(&mut self) {
+        self.sector = 0;
+        self.address = 0;
+        self.command = 0;
+        self.status = 0;
+        self.control = 0;
+        self.remaining_cycles = 0;
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if self.status & STATUS_BUSY != 0 {
+            self.remaining_cycles = self.remaining_cycles.saturating_sub(cycles);
+        }
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        if self.status & STATUS_DONE != 0 && self.control & CONTROL_INTERRUPT_ENABLE != 0 {
+            self.interrupt_vector
+        } else {
+            None
+        }
+    }
+
+    fn wants_bus(&self) -> bool {
+        self.status & STATUS_BUSY != 0 && self.remaining_cycles == 0
+    }
+
+    fn master_bus(&mut self, bus: &mut Bus) {
+        self.status = if self.transfer(bus) {
+            STATUS_DONE
+        } else {
+            STATUS_DONE | STATUS_ERROR
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{cpu::CPU, instructions, memory::Memory};
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
+
+    /// Returns a disk of the given number of sectors, each filled with its own number.
+    fn disk(sector_count: u8) -> DiskDevice<Cursor<Vec<u8>>> {
+        let image = (0..sector_count)
+            .flat_map(|sector| vec![sector; SECTOR_SIZE])
+            .collect();
+
+        DiskDevice::new(Cursor::new(image)).unwrap()
+    }
+
+    fn memory_mapper() -> MemoryMapper {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+    }
+
+    #[test]
+    fn read_write_test() {
+        let disk = Rc::new(RefCell::new(disk(4)));
+        disk.borrow_mut().set_latency(2);
+
+        let mut memory_mapper = memory_mapper();
+        memory_mapper
+            .map(Box::new(disk.clone()), 0x4000, 0x4009, true)
+            .unwrap();
+
+        memory_mapper.set_u16(0x4000, 2).unwrap();
+        memory_mapper.set_u16(0x4002, 0x8000).unwrap();
+        memory_mapper.set_u16(0x4004, COMMAND_READ).unwrap();
+        memory_mapper.tick(1);
+        assert_eq!(memory_mapper.get_u16(0x4006), Ok(STATUS_BUSY));
+        assert_eq!(memory_mapper.get_u8(0x81FF), Ok(0x00));
+
+        memory_mapper.tick(1);
+        assert_eq!(memory_mapper.get_u16(0x4006), Ok(STATUS_DONE));
+        assert_eq!(memory_mapper.get_u8(0x8000), Ok(0x02));
+        assert_eq!(memory_mapper.get_u8(0x81FF), Ok(0x02));
+        assert_eq!(memory_mapper.get_u8(0x8200), Ok(0x00));
+
+        memory_mapper.set_u16(0x4000, 3).unwrap();
+        memory_mapper.set_u16(0x4004, COMMAND_WRITE).unwrap();
+        memory_mapper.tick(2);
+        assert_eq!(memory_mapper.get_u16(0x4006), Ok(STATUS_DONE));
+        assert_eq!(
+            disk.borrow().storage().get_ref()[3 * SECTOR_SIZE..],
+            [0x02; SECTOR_SIZE]
+        );
+
+        memory_mapper.set_u16(0x4000, 4).unwrap();
+        memory_mapper.set_u16(0x4004, COMMAND_READ).unwrap();
+        memory_mapper.tick(2);
+        assert_eq!(
+            memory_mapper.get_u16(0x4006),
+            Ok(STATUS_DONE | STATUS_ERROR)
+        );
+    }
+
+    #[test]
+    fn busy_command_test() {
+        let mut memory_mapper = memory_mapper();
+        let mut disk = disk(4);
+        disk.set_latency(2);
+        memory_mapper
+            .map(Box::new(disk), 0x4000, 0x4009, true)
+            .unwrap();
+
+        memory_mapper.set_u16(0x4000, 1).unwrap();
+        memory_mapper.set_u16(0x4002, 0x8000).unwrap();
+        memory_mapper.set_u16(0x4004, COMMAND_READ).unwrap();
+        memory_mapper.tick(1);
+
+        // Neither byte of the command changes during the transfer
+        memory_mapper.set_u8(0x4004, 0x01).unwrap();
+        memory_mapper.set_u8(0x4005, COMMAND_WRITE as u8).unwrap();
+        assert_eq!(memory_mapper.get_u16(0x4004), Ok(COMMAND_READ));
+
+        memory_mapper.tick(1);
+        assert_eq!(memory_mapper.get_u16(0x4006), Ok(STATUS_DONE));
+        assert_eq!(memory_mapper.get_u8(0x8000), Ok(0x01));
+    }
+
+    #[test]
+    fn boot_test() {
+        let mut image = vec![0; 2 * SECTOR_SIZE];
+        #[rustfmt::skip]
+        let boot_sector = [
+            instructions::MOV_LIT_MEM, 0x00, 0x01, 0x40, 0x00, // mov $0001, &4000
+            instructions::MOV_LIT_MEM, 0x80, 0x00, 0x40, 0x02, // mov $8000, &4002
+            instructions::MOV_LIT_MEM, 0x00, 0x01, 0x40, 0x08, // mov $0001, &4008
+            instructions::MOV_LIT_MEM, 0x00, 0x01, 0x40, 0x04, // mov $0001, &4004
+            instructions::INC_REG, 2,                          // inc r1
+            instructions::JMP_NOT_EQ, 0x00, 0x01, 0x00, 0x14,  // jne $1, &0014
+        ];
+        image[..boot_sector.len()].copy_from_slice(&boot_sector);
+        image[SECTOR_SIZE..SECTOR_SIZE + 2].copy_from_slice(&[0x12, 0x34]);
+
+        let mut disk = DiskDevice::new(Cursor::new(image)).unwrap();
+        disk.set_latency(10);
+        disk.set_interrupt_vector(Some(1));
+
+        let mut memory_mapper = memory_mapper();
+        disk.boot(&mut memory_mapper, 0x0000).unwrap();
+        memory_mapper
+            .map(Box::new(disk), 0x4000, 0x4009, true)
+            .unwrap();
+        memory_mapper
+            .load_image(&[instructions::HLT], 0x2000, "handler")
+            .unwrap();
+        memory_mapper.set_u16(0x1002, 0x2000).unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.memory_mut().get_u16(0x8000), Ok(0x1234));
+        assert!(cpu.get_register("r1").unwrap() > 0);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+    memory_mapper::Bus,
+};
+
+/// Source register: the first address copied, or the fill value in its low byte.
+pub const DMA_SOURCE: usize = 0x00;
+/// Destination register: the first address written.
+pub const DMA_DESTINATION: usize = 0x02;
+/// Length register: the number of bytes written.
+pub const DMA_LENGTH: usize = 0x04;
+/// Control register, made of the `CONTROL_*` flags.
+pub const DMA_CONTROL: usize = 0x06;
+/// Status register, made of the `STATUS_*` flags. Writing clears the done and error flags.
+pub const DMA_STATUS: usize = 0x08;
+
+/// Control bit that starts a transfer. It always reads as 0.
+pub const CONTROL_START: u16 = 0x01;
+/// Control bit that fills the destination with the source value instead of copying.
+pub const CONTROL_FILL: u16 = 0x02;
+/// Control bit that enables the completion interrupt.
+pub const CONTROL_INTERRUPT_ENABLE: u16 = 0x04;
+
+/// Status bit set while a transfer is in progress.
+pub const STATUS_BUSY: u16 = 0x01;
+/// Status bit set when a transfer completes.
+pub const STATUS_DONE: u16 = 0x02;
+/// Status bit set when a transfer stops at an address that cannot be accessed, along with the done bit.
+pub const STATUS_ERROR: u16 = 0x04;
+
+/// A DMA controller copying or filling memory across the regions of the memory mapper.
+///
+/// By default a transfer completes at the end of the instruction starting it.
+/// With a rate set, it moves that many bytes per cycle while the CPU keeps running.
+#[derive(Clone, Debug, Default)]
+pub struct DmaDevice {
+    source: u16,
+    destination: u16,
+    length: u16,
+    control: u16,
+    status: u16,
+    bytes_per_cycle: Option<u64>,
+    transferred: u16,
+    budget: u64,
+    interrupt_vector: Option<u16>,
+}
+
+impl DmaDevice {
+    pub fn new() -> DmaDevice {
+        DmaDevice::default()
+    }
+
+    /// Sets the number of bytes moved per cycle, or None for instant transfers.
+    pub fn set_bytes_per_cycle(&mut self, bytes_per_cycle: Option<u64>) {
+        self.bytes_per_cycle = bytes_per_cycle;
+    }
+
+    /// Sets the interrupt vector requested when a transfer completes.
+    pub fn set_interrupt_vector(&mut self, interrupt_vector: Option<u16>) {
+        self.interrupt_vector = interrupt_vector;
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.status & STATUS_BUSY != 0
+    }
+
+    /// Moves the byte at the given offset of the transfer.
+    fn transfer_byte(&self, bus: &mut Bus, offset: u16) -> Result<(), VmError> {
+        let value = if self.control & CONTROL_FILL != 0 {
+            self.source.to_be_bytes()[1]
+        } else {
+            bus.get_u8(usize::from(self.source) + usize::from(offset))?
+        };
+
+        bus.set_u8(usize::from(self.destination) + usize::from(offset), value)
+    }
+}
+
+impl WordRegisters for DmaDevice {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            DMA_SOURCE => Ok(self.source),
+            DMA_DESTINATION => Ok(self.destination),
+            DMA_LENGTH => Ok(self.length),
+            DMA_CONTROL => Ok(self.control),
+            DMA_STATUS => Ok(self.status),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        // The registers cannot change during a transfer
+        if self.is_busy() {
+            return match address {
+                DMA_SOURCE | DMA_DESTINATION | DMA_LENGTH | DMA_CONTROL | DMA_STATUS => Ok(()),
+                _ => Err(VmError::OutOfBounds { address }),
+            };
+        }
+
+        match address {
+            DMA_SOURCE => self.source = value,
+            DMA_DESTINATION => self.destination = value,
+            DMA_LENGTH => self.length = value,
+            DMA_CONTROL => {
+                self.control = value & !CONTROL_START;
+
+                if value & CONTROL_START != 0 {
+                    self.status = STATUS_BUSY;
+                    self.transferred = 0;
+                    self.budget = 0;
+                }
+            }
+            DMA_STATUS => self.status = 0,
+            _ => return Err(VmError::OutOfBounds { address }),
+        }
+
+        Ok(())
+    }
+}
+
+impl Device for DmaDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.set_register_u8(address, value)
+    }
+
+    fn reset(&mut self) {
+        *self = DmaDevice {
+            bytes_per_cycle: self.bytes_per_cycle,
+            interrupt_vector: self.interrupt_vector,
+            ..DmaDevice::default()
+        };
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if let (true, Some(bytes_per_cycle)) = (self.is_busy(), self.bytes_per_cycle) {
+            self.budget = self
+                .budget
+                .saturating_add(cycles.saturating_mul(bytes_per_cycle));
+        }
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        if self.status & STATUS_DONE != 0 && self.control & CONTROL_INTERRUPT_ENABLE != 0 {
+            self.interrupt_vector
+        } else {
+            None
+        }
+    }
+
+    fn wants_bus(&self) -> bool {
+        self.is_busy() && (self.bytes_per_cycle.is_none() || self.budget > 0)
+    }
+
+    fn master_bus(&mut self, bus: &mut Bus) {
+        let remaining = u64::from(self.length - self.transferred);
+        let count = match self.bytes_per_cycle {
+            Some(_) => remaining.min(self.budget),
+            None => remaining,
+        };
+
+        for _ in 0..count {
+            if self.transfer_byte(bus, self.transferred).is_err() {
+                self.status = STATUS_DONE | STATUS_ERROR;
+                return;
+            }
+            self.transferred += 1;
+        }
+
+        self.budget -= self.budget.min(count);
+        if self.transferred == self.length {
+            self.status = STATUS_DONE;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        memory::Memory, memory_mapper::MemoryMapper, screen_backend::BufferBackend,
+        screen_device::ScreenDevice,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn memory_mapper(dma: DmaDevice) -> MemoryMapper {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(dma), 0x4000, 0x4009, true)
+            .unwrap();
+        memory_mapper
+    }
+
+    #[test]
+    fn copy_to_screen_test() {
+        let screen = Rc::new(RefCell::new(ScreenDevice::with_backend(
+            BufferBackend::new(16, 16),
+        )));
+
+        let mut memory_mapper = memory_mapper(DmaDevice::new());
+        memory_mapper
+            .map(Box::new(screen.clone()), 0x3000, 0x30FF, true)
+            .unwrap();
+        memory_mapper.load_image(b"hello", 0x8000, "text").unwrap();
+
+        memory_mapper.set_u16(0x4000, 0x8000).unwrap();
+        memory_mapper.set_u16(0x4002, 0x3010).unwrap();
+        memory_mapper.set_u16(0x4004, 5).unwrap();
+        memory_mapper.set_u16(0x4006, CONTROL_START).unwrap();
+        memory_mapper.tick(1);
+
+        assert_eq!(memory_mapper.get_u16(0x4008), Ok(STATUS_DONE));
+        assert_eq!(memory_mapper.get_u16(0x4006), Ok(0));
+        assert_eq!(screen.borrow().backend().row_text(1).trim_end(), "hello");
+    }
+
+    #[test]
+    fn fill_over_cycles_test() {
+        let mut dma = DmaDevice::new();
+        dma.set_bytes_per_cycle(Some(2));
+        dma.set_interrupt_vector(Some(3));
+
+        let mut memory_mapper = memory_mapper(dma);
+        memory_mapper.set_u16(0x4000, 0x00AA).unwrap();
+        memory_mapper.set_u16(0x4002, 0x8000).unwrap();
+        memory_mapper.set_u16(0x4004, 5).unwrap();
+        memory_mapper
+            .set_u16(
+                0x4006,
+                CONTROL_START | CONTROL_FILL | CONTROL_INTERRUPT_ENABLE,
+            )
+            .unwrap();
+
+        memory_mapper.tick(1);
+        assert_eq!(memory_mapper.get_u16(0x8000), Ok(0xAAAA));
+        assert_eq!(memory_mapper.get_u8(0x8002), Ok(0x00));
+        assert_eq!(memory_mapper.get_u16(0x4008), Ok(STATUS_BUSY));
+
+        memory_mapper.tick(2);
+        assert_eq!(memory_mapper.get_u8(0x8004), Ok(0xAA));
+        assert_eq!(memory_mapper.get_u8(0x8005), Ok(0x00));
+        assert_eq!(memory_mapper.get_u16(0x4008), Ok(STATUS_DONE));
+        assert_eq!(memory_mapper.irq_pending(), 1 << 3);
+    }
+
+    #[test]
+    fn unmapped_destination_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x100), 0x0000, 0x00FF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(DmaDevice::new()), 0x4000, 0x4009, true)
+            .unwrap();
+
+        memory_mapper.set_u16(0x4002, 0x00F0).unwrap();
+        memory_mapper.set_u16(0x4004, 0x20).unwrap();
+        memory_mapper.set_u16(0x4006, CONTROL_START).unwrap();
+        memory_mapper.tick(1);
+
+        assert_eq!(
+            memory_mapper.get_u16(0x4008),
+            Ok(STATUS_DONE | STATUS_ERROR)
+        );
+    }
+}
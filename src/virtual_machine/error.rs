@@ -0,0 +1,118 @@
+use crate::virtual_machine::mmu::Access;
+use std::{error::Error, fmt};
+
+/// Errors raised by the virtual machine and its devices.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmError {
+    /// No memory region is mapped at the address.
+    Unmapped { address: usize },
+    /// A new mapping from `start` to `end` overlaps the existing region named `name`.
+    Overlap {
+        start: usize,
+        end: usize,
+        name: String,
+    },
+    /// No mapped region matches the given handle or name.
+    UnknownRegion,
+    /// The bytes of the u16 access at the address belong to different regions.
+    Unaligned { address: usize },
+    /// The u16 access at the device address does not start at a register boundary.
+    MisalignedRegister { address: usize },
+    /// The device address is past the end of the device.
+    OutOfBounds { address: usize },
+    /// The device address lies in a write-protected range.
+    ReadOnly { address: usize },
+    /// The device holding the bus accessed its own registers at the address.
+    BusConflict { address: usize },
+    /// The byte at `ip` is not a known instruction.
+    IllegalOpcode { ip: u16, opcode: u8 },
+    /// An instruction operand at `ip` does not encode a register.
+    InvalidRegister { ip: u16, index: u8 },
+    /// No register has the given name.
+    UnknownRegister { name: String },
+    /// The CPU already executed a halt instruction.
+    Halted,
+    /// A device rejected the access at the given device address.
+    Device { address: usize, message: String },
+    /// The page holding the virtual address does not allow the access.
+    PageFault { address: usize, access: Access },
+    /// The `INT` instruction at `ip` runs inside an interrupt handler, and handlers do not nest.
+    NestedInterrupt { ip: u16 },
+    /// Entering the handler of the interrupt `vector` faulted on the stack at `sp`.
+    DoubleFault { vector: u16, sp: u16 },
+    /// The instruction at `ip` is only allowed in supervisor mode.
+    PrivilegedInstruction { ip: u16, opcode: u8 },
+    /// The `SYS` instruction at `ip` calls a number with no registered host function.
+    UnknownHostCall { ip: u16, number: u16 },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::Unmapped { address } => {
+                write!(f, "No memory region found for address {:#06X}", address)
+            }
+            VmError::Overlap { start, end, name } => write!(
+                f,
+                "Region {:#06X}-{:#06X} overlaps region '{}'",
+                start, end, name
+            ),
+            VmError::UnknownRegion => write!(f, "No such memory region"),
+            VmError::Unaligned { address } => write!(
+                f,
+                "The u16 access at address {:#06X} crosses a region boundary",
+                address
+            ),
+            VmError::MisalignedRegister { address } => write!(
+                f,
+                "The u16 access at device address {:#06X} is not aligned to a register",
+                address
+            ),
+            VmError::OutOfBounds { address } => {
+                write!(f, "Address {:#06X} is out of bounds", address)
+            }
+            VmError::ReadOnly { address } => {
+                write!(f, "Address {:#06X} is read-only", address)
+            }
+            VmError::BusConflict { address } => write!(
+                f,
+                "The device holding the bus accessed its own registers at address {:#06X}",
+                address
+            ),
+            VmError::IllegalOpcode { ip, opcode } => {
+                write!(f, "Illegal opcode {:#04X} at address {:#06X}", opcode, ip)
+            }
+            VmError::InvalidRegister { ip, index } => {
+                write!(f, "No such register index {} at address {:#06X}", index, ip)
+            }
+            VmError::UnknownRegister { name } => write!(f, "No such register '{}'", name),
+            VmError::Halted => write!(f, "The CPU is halted"),
+            VmError::Device { address, message } => {
+                write!(f, "Device error at address {:#06X}: {}", address, message)
+            }
+            VmError::PageFault { address, access } => {
+                write!(f, "Page fault on {} at address {:#06X}", access, address)
+            }
+            VmError::NestedInterrupt { ip } => write!(
+                f,
+                "Software interrupt at address {:#06X} inside an interrupt handler",
+                ip
+            ),
+            VmError::DoubleFault { vector, sp } => write!(
+                f,
+                "Interrupt {} cannot push its state onto the stack at address {:#06X}",
+                vector, sp
+            ),
+            VmError::PrivilegedInstruction { ip, opcode } => write!(
+                f,
+                "Privileged opcode {:#04X} at address {:#06X} in user mode",
+                opcode, ip
+            ),
+            VmError::UnknownHostCall { ip, number } => {
+                write!(f, "No host call number {} at address {:#06X}", number, ip)
+            }
+        }
+    }
+}
+
+impl Error for VmError {}
@@ -0,0 +1,307 @@
+use crate::virtual_machine::{device::Device, error::VmError};
+use std::io::{self, Write};
+
+/// Width in pixels of the framebuffer.
+pub const FRAMEBUFFER_WIDTH: usize = 128;
+/// Height in pixels of the framebuffer.
+pub const FRAMEBUFFER_HEIGHT: usize = 96;
+
+/// Address of the palette, right after the pixels.
+/// Each of the 256 entries holds a red, a green and a blue byte.
+pub const FRAMEBUFFER_PALETTE: usize = FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT;
+/// Number of bytes the framebuffer occupies.
+pub const FRAMEBUFFER_SIZE: usize = FRAMEBUFFER_PALETTE + PALETTE_SIZE * 3;
+
+/// Number of entries of the palette.
+const PALETTE_SIZE: usize = 256;
+
+/// A bitmap display holding one palette index per pixel, row by row.
+///
+/// The palette starts as the 256 colours of xterm, so the first 16 entries
+/// match the colours of `ScreenDevice`.
+#[derive(Clone)]
+pub struct FramebufferDevice {
+    pixels: Box<[u8]>,
+    palette: [[u8; 3]; PALETTE_SIZE],
+}
+
+impl Default for FramebufferDevice {
+    fn default() -> FramebufferDevice {
+        FramebufferDevice::new()
+    }
+}
+
+impl FramebufferDevice {
+    pub fn new() -> FramebufferDevice {
+        FramebufferDevice {
+            pixels: vec![0; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT].into_boxed_slice(),
+            palette: default_palette(),
+        }
+    }
+
+    /// Returns the palette index of the pixel at the given position.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x < FRAMEBUFFER_WIDTH && y < FRAMEBUFFER_HEIGHT {
+            Some(self.pixels[y * FRAMEBUFFER_WIDTH + x])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the colour of the given palette entry.
+    pub fn palette_entry(&self, index: u8) -> [u8; 3] {
+        self.palette[usize::from(index)]
+    }
+
+    /// Returns the red, green and blue bytes of every pixel, row by row.
+    pub fn rgb_pixels(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&index| self.palette_entry(index).to_vec())
+            .collect()
+    }
+
+    /// Writes the contents of the framebuffer as a binary PPM image.
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        write!(
+            writer,
+            "P6\n{} {}\n255\n",
+            FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT
+        )?;
+        writer.write_all(&self.rgb_pixels())
+    }
+
+    /// Writes the contents of the framebuffer as an uncompressed PNG image.
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(FRAMEBUFFER_WIDTH as u32).to_be_bytes());
+        header.extend_from_slice(&(FRAMEBUFFER_HEIGHT as u32).to_be_bytes());
+        // 8-bit RGB, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        // Every row starts with its filter type, 0 for none
+        let rgb_pixels = self.rgb_pixels();
+        let mut scanlines = Vec::with_capacity(rgb_pixels.len() + FRAMEBUFFER_HEIGHT);
+        for row in rgb_pixels.chunks(FRAMEBUFFER_WIDTH * 3) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_png_chunk(writer, b"IHDR", &header)?;
+        write_png_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+        write_png_chunk(writer, b"IEND", &[])
+    }
+}
+
+impl Device for FramebufferDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        Ok(u16::from_be_bytes([
+            self.get_u8(address)?,
+            self.get_u8(address + 1)?,
+        ]))
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        if address < FRAMEBUFFER_PALETTE {
+            Ok(self.pixels[address])
+        } else if address < FRAMEBUFFER_SIZE {
+            let offset = address - FRAMEBUFFER_PALETTE;
+            Ok(self.palette[offset / 3][offset % 3])
+        } else {
+            Err(VmError::OutOfBounds { address })
+        }
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        let [high, low] = value.to_be_bytes();
+        self.set_u8(address, high)?;
+        self.set_u8(address + 1, low)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        if address < FRAMEBUFFER_PALETTE {
+            self.pixels[address] = value;
+        } else if address < FRAMEBUFFER_SIZE {
+            let offset = address - FRAMEBUFFER_PALETTE;
+            self.palette[offset / 3][offset % 3] = value;
+        } else {
+            return Err(VmError::OutOfBounds { address });
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        *self = FramebufferDevice::new();
+    }
+}
+
+/// Returns the 256 colours of xterm: 16 base colours, a 6×6×6 cube and 24 greys.
+fn default_palette() -> [[u8; 3]; PALETTE_SIZE] {
+    const BASE: [[u8; 3]; 16] = [
+        [0x00, 0x00, 0x00],
+        [0x80, 0x00, 0x00],
+        [0x00, 0x80, 0x00],
+        [0x80, 0x80, 0x00],
+        [0x00, 0x00, 0x80],
+        [0x80, 0x00, 0x80],
+        [0x00, 0x80, 0x80],
+        [0xC0, 0xC0, 0xC0],
+        [0x80, 0x80, 0x80],
+        [0xFF, 0x00, 0x00],
+        [0x00, 0xFF, 0x00],
+        [0xFF, 0xFF, 0x00],
+        [0x00, 0x00, 0xFF],
+        [0xFF, 0x00, 0xFF],
+        [0x00, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xFF],
+    ];
+    const LEVELS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+
+    let mut palette = [[0; 3]; PALETTE_SIZE];
+    palette[..16].copy_from_slice(&BASE);
+
+    for (index, entry) in palette[16..232].iter_mut().enumerate() {
+        *entry = [LEVELS[index / 36], LEVELS[index / 6 % 6], LEVELS[index % 6]];
+    }
+    for (index, entry) in palette[232..].iter_mut().enumerate() {
+        let grey = 8 + 10 * index as u8;
+        *entry = [grey; 3];
+    }
+
+    palette
+}
+
+/// Writes a PNG chunk: length, type, data and CRC of the type and data.
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), io::Error> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = !crc32(crc32(!0, kind), data);
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// Continues the CRC-32 used by PNG over the given bytes.
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Wraps the given bytes in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(bytes: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK_SIZE: usize = 0xFFFF;
+
+    let mut stream = vec![0x78, 0x01];
+    let block_count = bytes.len().max(1).div_ceil(MAX_BLOCK_SIZE);
+
+    for (index, block) in bytes
+        .chunks(MAX_BLOCK_SIZE)
+        .chain(bytes.is_empty().then_some(&[][..]))
+        .enumerate()
+    {
+        let is_final = index + 1 == block_count;
+        let length = block.len() as u16;
+
+        stream.push(is_final as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(bytes).to_be_bytes());
+    stream
+}
+
+/// Returns the Adler-32 checksum of the given bytes.
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{memory::Memory, memory_mapper::MemoryMapper};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn pixels_and_palette_test() {
+        let framebuffer = Rc::new(RefCell::new(FramebufferDevice::new()));
+
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(
+                Box::new(framebuffer.clone()),
+                0x8000,
+                0x8000 + FRAMEBUFFER_SIZE - 1,
+                true,
+            )
+            .unwrap();
+
+        memory_mapper.set_u16(0x8000 + 128 + 2, 0x0109).unwrap();
+        memory_mapper
+            .load_image(
+                &[0x12, 0x34, 0x56],
+                0x8000 + FRAMEBUFFER_PALETTE + 3,
+                "palette",
+            )
+            .unwrap();
+
+        let framebuffer = framebuffer.borrow();
+        assert_eq!(framebuffer.pixel(2, 1), Some(0x01));
+        assert_eq!(framebuffer.pixel(3, 1), Some(0x09));
+        assert_eq!(framebuffer.pixel(128, 0), None);
+        assert_eq!(framebuffer.palette_entry(1), [0x12, 0x34, 0x56]);
+        assert_eq!(framebuffer.palette_entry(9), [0xFF, 0x00, 0x00]);
+        assert_eq!(framebuffer.palette_entry(196), [0xFF, 0x00, 0x00]);
+        assert_eq!(framebuffer.palette_entry(255), [0xEE, 0xEE, 0xEE]);
+    }
+
+    #[test]
+    fn ppm_test() {
+        let mut framebuffer = FramebufferDevice::new();
+        framebuffer.set_u8(1, 15).unwrap();
+
+        let mut image = Vec::new();
+        framebuffer.write_ppm(&mut image).unwrap();
+
+        let header = b"P6\n128 96\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + FRAMEBUFFER_PALETTE * 3);
+        assert_eq!(
+            &image[header.len()..header.len() + 6],
+            &[0, 0, 0, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn png_test() {
+        let mut image = Vec::new();
+        FramebufferDevice::new().write_png(&mut image).unwrap();
+
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&image[12..16], b"IHDR");
+        assert_eq!(&image[image.len() - 8..image.len() - 4], b"IEND");
+        // CRC of an empty IEND chunk
+        assert_eq!(&image[image.len() - 4..], &[0xAE, 0x42, 0x60, 0x82]);
+
+        assert_eq!(crc32(!0, b"123456789"), !0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
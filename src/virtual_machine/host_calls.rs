@@ -0,0 +1,389 @@
+use crate::virtual_machine::{cpu::CPU, error::VmError, mmu::Access, registers::Register};
+use std::{
+    cell::RefCell,
+    fs,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+/// Halts the CPU with the exit status in r1.
+pub const SYS_EXIT: u16 = 0x00;
+/// Writes the low byte of r1 to the output.
+pub const SYS_PUTC: u16 = 0x01;
+/// Reads a byte from the input into `acc`, or `HOST_CALL_ERROR` at the end of the input.
+pub const SYS_GETC: u16 = 0x02;
+/// Reads the file whose NUL-terminated path is at r1 into the buffer at r2, of r3 bytes at most.
+/// Returns the number of bytes read in `acc`, never more than `MAX_TRANSFER_LENGTH`.
+pub const SYS_READ_FILE: u16 = 0x03;
+/// Writes r3 bytes of the buffer at r2 to the file whose NUL-terminated path is at r1.
+/// Returns the number of bytes written in `acc`. Fails if r3 is over `MAX_TRANSFER_LENGTH`.
+pub const SYS_WRITE_FILE: u16 = 0x04;
+
+/// Value returned in `acc` when a standard call fails on the host.
+pub const HOST_CALL_ERROR: u16 = 0xFFFF;
+
+/// Largest number of bytes the file calls transfer, so that a count never reads as `HOST_CALL_ERROR`.
+pub const MAX_TRANSFER_LENGTH: u16 = HOST_CALL_ERROR - 1;
+
+/// Longest path accepted by the file calls, terminator included.
+const MAX_PATH_LENGTH: usize = 256;
+
+/// Registers the standard host calls, reading and writing bytes through the given streams.
+/// File paths are relative to the given sandbox root. Absolute paths and paths leaving the
+/// root are rejected, but symbolic links inside the root are followed.
+pub fn register_standard_host_calls<R, W, P>(cpu: &mut CPU, input: R, output: W, root: P)
+where
+    R: Read + 'static,
+    W: Write + 'static,
+    P: AsRef<Path>,
+{
+    let input = Rc::new(RefCell::new(input));
+    let output = Rc::new(RefCell::new(output));
+    let read_root = root.as_ref().to_path_buf();
+    let write_root = read_root.clone();
+
+    cpu.register_host_call(SYS_EXIT, |cpu| {
+        let status = cpu.registers().get(Register::R1);
+        cpu.exit(status);
+        Ok(())
+    });
+
+    cpu.register_host_call(SYS_PUTC, move |cpu| {
+        let byte = cpu.registers().get(Register::R1) as u8;
+        let mut output = output.borrow_mut();
+        let result = match output.write_all(&[byte]).and_then(|_| output.flush()) {
+            Ok(()) => 0,
+            Err(_) => HOST_CALL_ERROR,
+        };
+
+        cpu.registers_mut().set(Register::Acc, result);
+        Ok(())
+    });
+
+    cpu.register_host_call(SYS_GETC, move |cpu| {
+        let mut byte = [0];
+        let result = match input.borrow_mut().read(&mut byte) {
+            Ok(1) => u16::from(byte[0]),
+            _ => HOST_CALL_ERROR,
+        };
+
+        cpu.registers_mut().set(Register::Acc, result);
+        Ok(())
+    });
+
+    cpu.register_host_call(SYS_READ_FILE, move |cpu| {
+        let path = read_path(cpu, &read_root)?;
+        let buffer = usize::from(cpu.registers().get(Register::R2));
+        let capacity = usize::from(cpu.registers().get(Register::R3).min(MAX_TRANSFER_LENGTH));
+        // A fault while writing the buffer would read the file again
+        cpu.check_bytes(buffer, capacity, Access::Write)?;
+
+        let result = match path.map(fs::read) {
+            Some(Ok(mut bytes)) => {
+                bytes.truncate(capacity);
+                cpu.write_bytes(buffer, &bytes)?;
+                bytes.len() as u16
+            }
+            _ => HOST_CALL_ERROR,
+        };
+
+        cpu.registers_mut().set(Register::Acc, result);
+        Ok(())
+    });
+
+    cpu.register_host_call(SYS_WRITE_FILE, move |cpu| {
+        let path = read_path(cpu, &write_root)?;
+        let buffer = usize::from(cpu.registers().get(Register::R2));
+        let length = cpu.registers().get(Register::R3);
+        if length > MAX_TRANSFER_LENGTH {
+            cpu.registers_mut().set(Register::Acc, HOST_CALL_ERROR);
+            return Ok(());
+        }
+        let bytes = cpu.read_bytes(buffer, usize::from(length))?;
+
+        let result = match path.map(|path| fs::write(path, bytes)) {
+            Some(Ok(())) => length,
+            _ => HOST_CALL_ERROR,
+        };
+
+        cpu.registers_mut().set(Register::Acc, result);
+        Ok(())
+    });
+}
+
+/// Reads the NUL-terminated path at r1 and resolves it in the sandbox root.
+/// Returns None if it is too long, not UTF-8 or outside the root.
+fn read_path(cpu: &mut CPU, root: &Path) -> Result<Option<PathBuf>, VmError> {
+    let address = usize::from(cpu.registers().get(Register::R1));
+    let mut path = Vec::new();
+
+    for offset in 0..MAX_PATH_LENGTH {
+        match cpu.read_bytes(address + offset, 1)?[0] {
+            0 => {
+                return Ok(String::from_utf8(path)
+                    .ok()
+                    .and_then(|path| sandboxed_path(root, Path::new(&path))))
+            }
+            byte => path.push(byte),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Joins the relative path to the root once its `.` and `..` components are resolved.
+/// Returns None if the path is absolute or leaves the root.
+fn sandboxed_path(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !relative.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(root.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        instructions,
+        interrupts::{DEFAULT_INTERRUPT_VECTOR_ADDRESS, PAGE_FAULT_VECTOR},
+        memory::Memory,
+        memory_mapper::MemoryMapper,
+        mmu::{PageTableEntry, PAGE_EXECUTE, PAGE_READ, PAGE_USER, PAGE_WRITE},
+    };
+    use std::{env, io};
+
+    /// Output that can still be inspected once given to the CPU.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, bytes: &[u8]) -> Result<usize, io::Error> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    fn cpu_with_program(program: &[u8], input: &[u8], output: SharedOutput) -> CPU {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .load_image(program, 0x0000, "program")
+            .unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        let input = io::Cursor::new(input.to_vec());
+        register_standard_host_calls(&mut cpu, input, output, env::temp_dir());
+        cpu
+    }
+
+    #[test]
+    fn echo_test() {
+        let output = SharedOutput::default();
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::SYS_LIT, 0x00, 0x02,                // sys $2
+            instructions::JEQ_LIT, 0xFF, 0xFF, 0x00, 0x13,    // jeq $ffff, &0013
+            instructions::MOV_REG_REG, 1, 2,                  // mov acc, r1
+            instructions::SYS_LIT, 0x00, 0x01,                // sys $1
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x00, // jne $ffff, &0000
+            instructions::MOV_LIT_REG, 0x00, 0x07, 2,         // mov $7, r1
+            instructions::SYS_LIT, 0x00, 0x00,                // sys $0
+            instructions::HLT,
+        ], b"hi", output.clone());
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.exit_status(), Some(7));
+        assert_eq!(cpu.get_register("ip"), Ok(0x1A));
+        assert_eq!(*output.0.borrow(), b"hi");
+    }
+
+    #[test]
+    fn file_test() {
+        let name = format!("vm16-host-call-{}.txt", std::process::id());
+        let path = env::temp_dir().join(&name);
+        let path_bytes = [name.as_bytes(), &[0]].concat();
+
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x80, 0x00, 2, // mov $8000, r1
+            instructions::MOV_LIT_REG, 0x90, 0x00, 3, // mov $9000, r2
+            instructions::MOV_LIT_REG, 0x00, 0x05, 4, // mov $5, r3
+            instructions::SYS_LIT, 0x00, 0x04,        // sys $4
+            instructions::MOV_LIT_REG, 0xA0, 0x00, 3, // mov $a000, r2
+            instructions::MOV_LIT_REG, 0x00, 0x03, 4, // mov $3, r3
+            instructions::SYS_LIT, 0x00, 0x03,        // sys $3
+            instructions::HLT,
+        ], b"", SharedOutput::default());
+        cpu.memory_mut()
+            .load_image(&path_bytes, 0x8000, "path")
+            .unwrap();
+        cpu.memory_mut()
+            .load_image(b"hello", 0x9000, "data")
+            .unwrap();
+
+        cpu.run().unwrap();
+        let written = fs::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written.unwrap(), b"hello");
+        assert_eq!(cpu.get_register("acc"), Ok(3));
+        assert_eq!(cpu.read_bytes(0xA000, 4), Ok(b"hel\0".to_vec()));
+    }
+
+    #[test]
+    fn buffer_fault_test() {
+        let name = format!("vm16-buffer-fault-{}.txt", std::process::id());
+        let path = env::temp_dir().join(&name);
+        fs::write(&path, b"hello").unwrap();
+        let path_bytes = [name.as_bytes(), &[0]].concat();
+
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x80, 0x00, 2, // mov $8000, r1
+            instructions::MOV_LIT_REG, 0x90, 0xFE, 3, // mov $90fe, r2
+            instructions::MOV_LIT_REG, 0x00, 0x05, 4, // mov $5, r3
+            instructions::MOV_LIT_REG, 0x04, 0x00, 5, // mov $0400, r4
+            instructions::LPT_REG, 5,                 // lpt r4
+            instructions::USR,                        // usr
+            instructions::SYS_LIT, 0x00, 0x03,        // sys $3
+        ], b"", SharedOutput::default());
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_REG_MEM, 2, 0x21, 0x00, // mov r1, &2100
+            instructions::HLT,
+        ];
+        let memory = cpu.memory_mut();
+        memory.load_image(&path_bytes, 0x8000, "path").unwrap();
+        memory.load_image(&handler, 0x2000, "handler").unwrap();
+        memory
+            .set_u16(
+                usize::from(DEFAULT_INTERRUPT_VECTOR_ADDRESS) + usize::from(PAGE_FAULT_VECTOR) * 2,
+                0x2000,
+            )
+            .unwrap();
+        for page in 0..=0xFF {
+            let flags = if page == 0x91 {
+                PAGE_READ | PAGE_USER
+            } else {
+                PAGE_READ | PAGE_WRITE | PAGE_EXECUTE | PAGE_USER
+            };
+            memory
+                .set_u16(
+                    0x0400 + usize::from(page) * 2,
+                    PageTableEntry::new(page, flags).0,
+                )
+                .unwrap();
+        }
+
+        let result = cpu.run();
+        fs::remove_file(&path).unwrap();
+
+        // The fault is raised before the first byte of the buffer is written
+        assert_eq!(result, Ok(()));
+        assert_eq!(cpu.memory_mut().get_u16(0x2100), Ok(0x9100));
+        assert_eq!(cpu.memory_mut().get_u16(0x90FE), Ok(0x0000));
+    }
+
+    #[test]
+    fn sandbox_test() {
+        let name = format!("vm16-sandbox-{}.txt", std::process::id());
+        let path = env::temp_dir().join(&name);
+        let absolute = [path.to_str().unwrap().as_bytes(), &[0]].concat();
+        let escaping = [format!("dir/../../{}", name).as_bytes(), &[0]].concat();
+
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0x80, 0x00, 2, // mov $8000, r1
+            instructions::MOV_LIT_REG, 0x00, 0x01, 4, // mov $1, r3
+            instructions::SYS_LIT, 0x00, 0x04,        // sys $4
+            instructions::MOV_REG_REG, 1, 5,          // mov acc, r4
+            instructions::MOV_LIT_REG, 0x81, 0x00, 2, // mov $8100, r1
+            instructions::SYS_LIT, 0x00, 0x04,        // sys $4
+            instructions::HLT,
+        ], b"", SharedOutput::default());
+        cpu.memory_mut()
+            .load_image(&absolute, 0x8000, "absolute")
+            .unwrap();
+        cpu.memory_mut()
+            .load_image(&escaping, 0x8100, "escaping")
+            .unwrap();
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_register("r4"), Ok(HOST_CALL_ERROR));
+        assert_eq!(cpu.get_register("acc"), Ok(HOST_CALL_ERROR));
+        assert!(!path.exists());
+        assert_eq!(
+            sandboxed_path(Path::new("root"), Path::new("./dir/../file")),
+            Some(PathBuf::from("root/file"))
+        );
+    }
+
+    #[test]
+    fn transfer_length_test() {
+        let name = format!("vm16-transfer-length-{}.txt", std::process::id());
+        let path = env::temp_dir().join(&name);
+        fs::write(&path, vec![0xAB; usize::from(HOST_CALL_ERROR)]).unwrap();
+        let path_bytes = [name.as_bytes(), &[0]].concat();
+
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::MOV_LIT_REG, 0xFF, 0x00, 2, // mov $ff00, r1
+            instructions::MOV_LIT_REG, 0x00, 0x01, 3, // mov $0001, r2
+            instructions::MOV_LIT_REG, 0xFF, 0xFF, 4, // mov $ffff, r3
+            instructions::SYS_LIT, 0x00, 0x04,        // sys $4
+            instructions::MOV_REG_REG, 1, 5,          // mov acc, r4
+            instructions::SYS_LIT, 0x00, 0x03,        // sys $3
+        ], b"", SharedOutput::default());
+        cpu.memory_mut()
+            .load_image(&path_bytes, 0xFF00, "path")
+            .unwrap();
+
+        // The read overwrites the program, so it runs one instruction at a time
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        let length = fs::metadata(&path).unwrap().len();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(cpu.get_register("r4"), Ok(HOST_CALL_ERROR));
+        assert_eq!(length, u64::from(HOST_CALL_ERROR));
+        assert_eq!(cpu.get_register("acc"), Ok(MAX_TRANSFER_LENGTH));
+    }
+
+    #[test]
+    fn host_call_error_test() {
+        #[rustfmt::skip]
+        let mut cpu = cpu_with_program(&[
+            instructions::SYS_LIT, 0x00, 0x02, // sys $2
+            instructions::SYS_LIT, 0x00, 0x03, // sys $3
+            instructions::HLT,
+        ], b"", SharedOutput::default());
+
+        assert_eq!(cpu.step(), Ok(false));
+        assert_eq!(cpu.get_register("acc"), Ok(HOST_CALL_ERROR));
+
+        // r1 points to an empty path
+        assert_eq!(cpu.step(), Ok(false));
+        assert_eq!(cpu.get_register("acc"), Ok(HOST_CALL_ERROR));
+    }
+}
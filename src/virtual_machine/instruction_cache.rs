@@ -0,0 +1,188 @@
+use crate::virtual_machine::{instructions, memory_mapper::PAGE_SIZE, registers::Register};
+
+/// Number of addresses in the 16-bit address space.
+const ADDRESS_COUNT: usize = 0x10000;
+
+/// Length in bytes of the longest instruction.
+pub const MAX_INSTRUCTION_LENGTH: usize = 5;
+
+/// The comparison made by a conditional jump between its operand and `acc`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    NotEqual,
+    Equal,
+    LessThan,
+    GreaterThan,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+impl Condition {
+    /// Returns true if the jump is taken.
+    pub fn holds(self, value: u16, acc: u16) -> bool {
+        match self {
+            Condition::NotEqual => value != acc,
+            Condition::Equal => value == acc,
+            Condition::LessThan => value < acc,
+            Condition::GreaterThan => value > acc,
+            Condition::LessOrEqual => value <= acc,
+            Condition::GreaterOrEqual => value >= acc,
+        }
+    }
+}
+
+/// An instruction with its operands decoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    MovLitReg(u16, Register),
+    MovRegReg(Register, Register),
+    MovRegMem(Register, u16),
+    MovMemReg(u16, Register),
+    MovLitMem(u16, u16),
+    MovRegPtrReg(Register, Register),
+    MovLitOffReg(u16, Register, Register),
+    AddRegReg(Register, Register),
+    AddLitReg(u16, Register),
+    SubLitReg(u16, Register),
+    SubRegLit(Register, u16),
+    SubRegReg(Register, Register),
+    MulLitReg(u16, Register),
+    MulRegReg(Register, Register),
+    IncReg(Register),
+    DecReg(Register),
+    LsfRegLit(Register, u8),
+    LsfRegReg(Register, Register),
+    RsfRegLit(Register, u8),
+    RsfRegReg(Register, Register),
+    AndRegLit(Register, u16),
+    AndRegReg(Register, Register),
+    OrRegLit(Register, u16),
+    OrRegReg(Register, Register),
+    XorRegLit(Register, u16),
+    XorRegReg(Register, Register),
+    Not(Register),
+    /// Jumps to the address if the condition holds between the literal and `acc`.
+    JmpLit(Condition, u16, u16),
+    /// Jumps to the address if the condition holds between the register and `acc`.
+    JmpReg(Condition, Register, u16),
+    PshLit(u16),
+    PshReg(Register),
+    Pop(Register),
+    CalLit(u16),
+    CalReg(Register),
+    Ret,
+    Int(u16),
+    Sys(u16),
+    RetInt,
+    Lpt(Register),
+    Usr,
+    Hlt,
+}
+
+impl Instruction {
+    /// Returns the opcode of the instruction and the register operand it writes, if any.
+    /// Results written to `acc` are not operands and are not reported.
+    pub fn register_write(&self) -> Option<(u8, Register)> {
+        match *self {
+            Instruction::MovLitReg(_, register) => Some((instructions::MOV_LIT_REG, register)),
+            Instruction::MovRegReg(_, register) => Some((instructions::MOV_REG_REG, register)),
+            Instruction::MovMemReg(_, register) => Some((instructions::MOV_MEM_REG, register)),
+            Instruction::MovRegPtrReg(_, register) => {
+                Some((instructions::MOV_REG_PTR_REG, register))
+            }
+            Instruction::MovLitOffReg(_, _, register) => {
+                Some((instructions::MOV_LIT_OFF_REG, register))
+            }
+            Instruction::IncReg(register) => Some((instructions::INC_REG, register)),
+            Instruction::DecReg(register) => Some((instructions::DEC_REG, register)),
+            Instruction::LsfRegLit(register, _) => Some((instructions::LSF_REG_LIT, register)),
+            Instruction::LsfRegReg(register, _) => Some((instructions::LSF_REG_REG, register)),
+            Instruction::RsfRegLit(register, _) => Some((instructions::RSF_REG_LIT, register)),
+            Instruction::RsfRegReg(register, _) => Some((instructions::RSF_REG_REG, register)),
+            Instruction::Pop(register) => Some((instructions::POP, register)),
+            _ => None,
+        }
+    }
+}
+
+/// An instruction decoded from memory, with what is needed to execute it again without fetching it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecodedInstruction {
+    pub opcode: u8,
+    pub instruction: Instruction,
+    pub length: u16,
+}
+
+/// Keeps the instructions decoded at each address.
+/// The owner is responsible for invalidating the instructions whose bytes are written.
+pub struct InstructionCache {
+    entries: Box<[Option<DecodedInstruction>]>,
+}
+
+impl Default for InstructionCache {
+    fn default() -> InstructionCache {
+        InstructionCache::new()
+    }
+}
+
+impl InstructionCache {
+    pub fn new() -> InstructionCache {
+        InstructionCache {
+            entries: vec![None; ADDRESS_COUNT].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the instruction decoded at the given address.
+    pub fn get(&self, address: u16) -> Option<DecodedInstruction> {
+        self.entries[address as usize]
+    }
+
+    /// Records the instruction decoded at the given address.
+    pub fn insert(&mut self, address: u16, decoded: DecodedInstruction) {
+        self.entries[address as usize] = Some(decoded);
+    }
+
+    /// Removes every instruction that may have a byte in the given mapper page.
+    pub fn invalidate_page(&mut self, page: usize) {
+        let start = (page * PAGE_SIZE).saturating_sub(MAX_INSTRUCTION_LENGTH - 1);
+        let end = ((page + 1) * PAGE_SIZE).min(ADDRESS_COUNT);
+
+        for entry in self.entries[start..end].iter_mut() {
+            *entry = None;
+        }
+    }
+
+    /// Removes every instruction.
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidate_page_test() {
+        let decoded = DecodedInstruction {
+            opcode: instructions::INC_REG,
+            instruction: Instruction::IncReg(Register::R1),
+            length: 2,
+        };
+        let mut cache = InstructionCache::new();
+        cache.insert(0x00FC, decoded);
+        cache.insert(0x00FB, decoded);
+        cache.insert(0x0150, decoded);
+        cache.insert(0x0200, decoded);
+
+        cache.invalidate_page(1);
+
+        // An instruction starting up to four bytes before the page may end in it
+        assert_eq!(cache.get(0x00FB), Some(decoded));
+        assert_eq!(cache.get(0x00FC), None);
+        assert_eq!(cache.get(0x0150), None);
+        assert_eq!(cache.get(0x0200), Some(decoded));
+    }
+}
@@ -47,4 +47,29 @@ pub const POP: u8             = 0x1A;
 pub const CAL_LIT: u8         = 0x5E;
 pub const CAL_REG: u8         = 0x5F;
 pub const RET: u8             = 0x60;
-pub const HLT: u8             = 0xFF;
\ No newline at end of file
+pub const HLT: u8             = 0xFF;
+
+pub const INT: u8             = 0xFD;
+pub const RET_INT: u8         = 0xFC;
+
+pub const LPT_REG: u8         = 0xFB;
+pub const USR: u8             = 0xFA;
+pub const SYS_LIT: u8         = 0xF9;
+
+/// Returns the number of cycles the instruction takes, not counting the wait states of the devices it accesses.
+pub fn cycle_cost(opcode: u8) -> u64 {
+    match opcode {
+        MOV_REG_REG | ADD_REG_REG | SUB_REG_REG | INC_REG | DEC_REG | LSF_REG_REG | RSF_REG_REG
+        | AND_REG_REG | OR_REG_REG | XOR_REG_REG | NOT | USR | HLT => 1,
+        MOV_LIT_REG | ADD_LIT_REG | SUB_LIT_REG | SUB_REG_LIT | LSF_REG_LIT | RSF_REG_LIT
+        | AND_REG_LIT | OR_REG_LIT | XOR_REG_LIT | LPT_REG => 2,
+        MOV_REG_MEM | MOV_MEM_REG | MOV_REG_PTR_REG | MOV_LIT_OFF_REG | PSH_LIT | PSH_REG | POP => 3,
+        JMP_NOT_EQ | JNE_REG | JEQ_REG | JEQ_LIT | JLT_REG | JLT_LIT | JGT_REG | JGT_LIT
+        | JLE_REG | JLE_LIT | JGE_REG | JGE_LIT => 3,
+        MOV_LIT_MEM | MUL_REG_REG | SYS_LIT => 4,
+        MUL_LIT_REG => 5,
+        // The CPU state takes ten words on the stack
+        CAL_LIT | CAL_REG | RET | INT | RET_INT => 12,
+        _ => 1,
+    }
+}
\ No newline at end of file
@@ -0,0 +1,49 @@
+use std::{cell::Cell, rc::Rc};
+
+/// Number of entries in the interrupt vector table.
+pub const INTERRUPT_VECTOR_COUNT: u16 = 16;
+
+/// Default address of the interrupt vector table.
+pub const DEFAULT_INTERRUPT_VECTOR_ADDRESS: u16 = 0x1000;
+
+/// Interrupt raised when a privileged instruction runs in user mode.
+/// The handler gets the address of the instruction in r1 and its opcode in r2.
+pub const GENERAL_PROTECTION_FAULT_VECTOR: u16 = 0xD;
+
+/// Interrupt raised when the MMU denies an access.
+/// The handler gets the virtual address in r1 and the access code in r2.
+pub const PAGE_FAULT_VECTOR: u16 = 0xE;
+
+/// A shared handle used by the host and devices to request hardware interrupts.
+///
+/// Every bit of the pending mask corresponds to an entry in the interrupt vector
+/// table. The CPU samples the pending requests between instructions.
+#[derive(Clone, Debug, Default)]
+pub struct IrqLine {
+    pending: Rc<Cell<u16>>,
+}
+
+impl IrqLine {
+    pub fn new() -> IrqLine {
+        IrqLine {
+            pending: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Requests the interrupt with the given vector index.
+    pub fn raise(&self, vector: u16) {
+        let bit = 1 << (vector % INTERRUPT_VECTOR_COUNT);
+        self.pending.set(self.pending.get() | bit);
+    }
+
+    /// Withdraws a pending request for the interrupt with the given vector index.
+    pub fn clear(&self, vector: u16) {
+        let bit = 1 << (vector % INTERRUPT_VECTOR_COUNT);
+        self.pending.set(self.pending.get() & !bit);
+    }
+
+    /// Returns the mask of all pending interrupt requests.
+    pub fn pending(&self) -> u16 {
+        self.pending.get()
+    }
+}
@@ -0,0 +1,532 @@
+use crate::virtual_machine::{device::Device, error::VmError};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    panic,
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard, Once, OnceLock, PoisonError,
+    },
+    thread,
+};
+
+/// Status register: bit 0 is set while a byte is available. Writing sets the control flags.
+pub const KEYBOARD_STATUS: usize = 0x00;
+/// Data register: reading pops the next byte, or returns 0 when none is available.
+pub const KEYBOARD_DATA: usize = 0x01;
+
+/// Status bit set while a byte is available.
+pub const STATUS_DATA_AVAILABLE: u8 = 0x01;
+/// Control bit that enables the keypress interrupt.
+pub const CONTROL_INTERRUPT_ENABLE: u8 = 0x02;
+
+/// Where a `KeyboardDevice` gets its input from.
+pub trait InputSource {
+    /// Returns the next available byte without blocking.
+    fn poll(&mut self) -> Option<u8>;
+}
+
+/// Input given in advance, e.g. by tests.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedInput {
+    bytes: VecDeque<u8>,
+}
+
+impl ScriptedInput {
+    pub fn new(bytes: &[u8]) -> ScriptedInput {
+        ScriptedInput {
+            bytes: bytes.iter().copied().collect(),
+        }
+    }
+
+    /// Queues the given bytes after the pending ones.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes.iter());
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+}
+
+/// Input read from any host byte stream by a background thread.
+///
+/// The thread stops at the end of the stream, or at its first read after the input is dropped.
+pub struct ReaderInput {
+    receiver: Receiver<u8>,
+}
+
+impl ReaderInput {
+    pub fn new<R: Read + Send + 'static>(mut reader: R) -> ReaderInput {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+
+            while let Ok(count @ 1..) = reader.read(&mut buffer) {
+                if buffer[..count]
+                    .iter()
+                    .any(|&byte| sender.send(byte).is_err())
+                {
+                    break;
+                }
+            }
+        });
+
+        ReaderInput { receiver }
+    }
+}
+
+impl InputSource for ReaderInput {
+    fn poll(&mut self) -> Option<u8> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// A host byte stream read by a single background thread on behalf of any number of inputs.
+///
+/// Every byte goes to each input subscribed when it is read. The bytes read while
+/// no input is subscribed are kept for the next one.
+struct SharedReader {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    senders: Vec<Sender<u8>>,
+    pending: VecDeque<u8>,
+}
+
+impl SharedReader {
+    fn new<R: Read + Send + 'static>(mut reader: R) -> SharedReader {
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+        let thread_subscribers = Arc::clone(&subscribers);
+
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+
+            while let Ok(count @ 1..) = reader.read(&mut buffer) {
+                let mut subscribers = lock(&thread_subscribers);
+
+                for &byte in &buffer[..count] {
+                    subscribers
+                        .senders
+                        .retain(|sender| sender.send(byte).is_ok());
+                    if subscribers.senders.is_empty() {
+                        subscribers.pending.push_back(byte);
+                    }
+                }
+            }
+        });
+
+        SharedReader { subscribers }
+    }
+
+    /// Returns a receiver of the bytes read from now on, starting with the pending ones.
+    fn subscribe(&self) -> Receiver<u8> {
+        let (sender, receiver) = mpsc::channel();
+        let mut subscribers = lock(&self.subscribers);
+
+        for byte in subscribers.pending.drain(..) {
+            let _ = sender.send(byte);
+        }
+        subscribers.senders.push(sender);
+
+        receiver
+    }
+}
+
+/// Returns the reader of the standard input shared by every `StdinInput`.
+fn stdin_reader() -> &'static SharedReader {
+    static STDIN_READER: OnceLock<SharedReader> = OnceLock::new();
+    STDIN_READER.get_or_init(|| SharedReader::new(io::stdin()))
+}
+
+/// The terminal settings from before raw mode, and the number of raw inputs using it.
+struct RawTerminal {
+    saved_settings: Option<String>,
+    users: usize,
+}
+
+static RAW_TERMINAL: Mutex<RawTerminal> = Mutex::new(RawTerminal {
+    saved_settings: None,
+    users: 0,
+});
+
+static RESTORE_TERMINAL_ON_PANIC: Once = Once::new();
+
+/// Locks the given mutex, even if a thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Input read from the standard input of the host.
+///
+/// A single thread reads the standard input for the whole process, so every
+/// input created, e.g. for a keyboard and a UART, gets every byte.
+pub struct StdinInput {
+    receiver: Receiver<u8>,
+    raw: bool,
+}
+
+impl StdinInput {
+    /// Reads the standard input as it is, usually line by line.
+    pub fn new() -> StdinInput {
+        StdinInput {
+            receiver: stdin_reader().subscribe(),
+            raw: false,
+        }
+    }
+
+    /// Puts the terminal in raw mode so that every keypress is read immediately and not echoed.
+    /// Raw mode relies on the `stty` command, and fails if it is missing or fails.
+    ///
+    /// The terminal settings are restored once every raw input is restored or dropped,
+    /// or when the process panics.
+    pub fn raw() -> Result<StdinInput, io::Error> {
+        let mut terminal = lock(&RAW_TERMINAL);
+
+        if terminal.users == 0 {
+            let saved_settings = stty(&["-g"])?;
+            stty(&["raw", "-echo"])?;
+            terminal.saved_settings = Some(saved_settings.trim().to_string());
+
+            RESTORE_TERMINAL_ON_PANIC.call_once(|| {
+                let previous_hook = panic::take_hook();
+                panic::set_hook(Box::new(move |info| {
+                    // Never wait on the lock, the panicking thread may hold it
+                    if let Ok(mut terminal) = RAW_TERMINAL.try_lock() {
+                        if let Some(saved_settings) = terminal.saved_settings.take() {
+                            let _ = stty(&[&saved_settings]);
+                        }
+                    }
+                    previous_hook(info);
+                }));
+            });
+        }
+        terminal.users += 1;
+
+        Ok(StdinInput {
+            receiver: stdin_reader().subscribe(),
+            raw: true,
+        })
+    }
+
+    /// Restores the terminal settings saved by `raw` if no other raw input is left.
+    /// Does nothing if the input is not in raw mode.
+    pub fn restore(&mut self) -> Result<(), io::Error> {
+        if !self.raw {
+            return Ok(());
+        }
+        self.raw = false;
+
+        let mut terminal = lock(&RAW_TERMINAL);
+        terminal.users -= 1;
+        if terminal.users > 0 {
+            return Ok(());
+        }
+
+        match terminal.saved_settings.take() {
+            Some(saved_settings) => stty(&[&saved_settings]).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for StdinInput {
+    fn default() -> StdinInput {
+        StdinInput::new()
+    }
+}
+
+impl Drop for StdinInput {
+    fn drop(&mut self) {
+        // There is nobody to return the failure to
+        if let Err(err) = self.restore() {
+            eprintln!("Failed to restore the terminal settings: {}", err);
+        }
+    }
+}
+
+impl InputSource for StdinInput {
+    fn poll(&mut self) -> Option<u8> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Runs stty on the terminal of the standard input and returns its output.
+fn stty(arguments: &[&str]) -> Result<String, io::Error> {
+    let output = Command::new("stty")
+        .args(arguments)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|err| io::Error::new(err.kind(), format!("Failed to run stty: {}", err)))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::other(format!(
+            "stty failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// A keyboard exposing a status register and a data register.
+///
+/// Bytes from the input source are buffered as the CPU runs. Reading the data
+/// register pops the oldest one. If an interrupt vector is set and the guest
+/// enables interrupts in the control register, the keyboard requests that
+/// interrupt while bytes are available.
+pub struct KeyboardDevice<S: InputSource = StdinInput> {
+    source: S,
+    buffer: VecDeque<u8>,
+    control: u8,
+    interrupt_vector: Option<u16>,
+}
+
+impl<S: InputSource> KeyboardDevice<S> {
+    pub fn new(source: S) -> KeyboardDevice<S> {
+        KeyboardDevice {
+            source,
+            buffer: VecDeque::new(),
+            control: 0,
+            interrupt_vector: None,
+        }
+    }
+
+    /// Sets the interrupt vector requested when a byte is available.
+    pub fn set_interrupt_vector(&mut self, interrupt_vector: Option<u16>) {
+        self.interrupt_vector = interrupt_vector;
+    }
+
+    /// Returns the input source for modification, e.g. to queue more scripted input.
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Moves the bytes available from the source into the buffer.
+    fn poll_source(&mut self) {
+        while let Some(byte) = self.source.poll() {
+            self.buffer.push_back(byte);
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let data_available = if self.buffer.is_empty() {
+            0
+        } else {
+            STATUS_DATA_AVAILABLE
+        };
+
+        data_available | self.control
+    }
+}
+
+impl<S: InputSource> Device for KeyboardDevice<S> {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        Ok(u16::from_be_bytes([
+            self.get_u8(address)?,
+            self.get_u8(address + 1)?,
+        ]))
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.poll_source();
+
+        match address {
+            KEYBOARD_STATUS => Ok(self.status()),
+            KEYBOARD_DATA => Ok(self.buffer.pop_front().unwrap_or(0)),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        let [high, low] = value.to_be_bytes();
+        self.set_u8(address, high)?;
+        self.set_u8(address + 1, low)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        match address {
+            KEYBOARD_STATUS => {
+                self.control = value & CONTROL_INTERRUPT_ENABLE;
+                Ok(())
+            }
+            KEYBOARD_DATA => Ok(()),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.control = 0;
+    }
+
+    fn tick(&mut self, _cycles: u64) {
+        self.poll_source();
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        if self.control & CONTROL_INTERRUPT_ENABLE != 0 && !self.buffer.is_empty() {
+            self.interrupt_vector
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        cpu::CPU, instructions, memory::Memory, memory_mapper::MemoryMapper,
+    };
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn registers_test() {
+        let mut keyboard = KeyboardDevice::new(ScriptedInput::new(b"hi"));
+
+        assert_eq!(keyboard.get_u8(KEYBOARD_STATUS), Ok(STATUS_DATA_AVAILABLE));
+        assert_eq!(keyboard.get_u8(KEYBOARD_DATA), Ok(b'h'));
+        assert_eq!(keyboard.get_u16(KEYBOARD_STATUS), Ok(0x0169));
+        assert_eq!(keyboard.get_u8(KEYBOARD_STATUS), Ok(0x00));
+        assert_eq!(keyboard.get_u8(KEYBOARD_DATA), Ok(0x00));
+        assert_eq!(
+            keyboard.get_u8(0x02),
+            Err(VmError::OutOfBounds { address: 0x02 })
+        );
+    }
+
+    /// Endless input that records when the reader thread drops it.
+    struct EndlessReader(Arc<AtomicBool>);
+
+    impl Read for EndlessReader {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+            thread::sleep(Duration::from_millis(1));
+            buffer[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    impl Drop for EndlessReader {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn reader_input_drop_test() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut input = ReaderInput::new(EndlessReader(Arc::clone(&stopped)));
+
+        while input.poll().is_none() {
+            thread::yield_now();
+        }
+        drop(input);
+
+        for _ in 0..1000 {
+            if stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("The reader thread did not stop");
+    }
+
+    /// Input that blocks until the test sends it some bytes.
+    struct ChannelReader(Receiver<Vec<u8>>);
+
+    impl Read for ChannelReader {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+            let bytes = self.0.recv().unwrap_or_default();
+            buffer[..bytes.len()].copy_from_slice(&bytes);
+            Ok(bytes.len())
+        }
+    }
+
+    /// Waits for the next byte of the given receiver.
+    fn receive(receiver: &Receiver<u8>) -> Option<u8> {
+        receiver.recv_timeout(Duration::from_secs(1)).ok()
+    }
+
+    #[test]
+    fn shared_reader_test() {
+        let (sender, receiver) = mpsc::channel();
+        let reader = SharedReader::new(ChannelReader(receiver));
+
+        let first = reader.subscribe();
+        let second = reader.subscribe();
+        sender.send(b"a".to_vec()).unwrap();
+        assert_eq!(receive(&first), Some(b'a'));
+        assert_eq!(receive(&second), Some(b'a'));
+
+        // Bytes read with nobody listening wait for the next input
+        drop(first);
+        drop(second);
+        sender.send(b"bc".to_vec()).unwrap();
+        while lock(&reader.subscribers).pending.len() < 2 {
+            thread::yield_now();
+        }
+
+        let third = reader.subscribe();
+        assert_eq!(receive(&third), Some(b'b'));
+        assert_eq!(receive(&third), Some(b'c'));
+    }
+
+    #[test]
+    fn keypress_interrupt_test() {
+        let keyboard = Rc::new(RefCell::new(KeyboardDevice::new(ScriptedInput::default())));
+        keyboard.borrow_mut().set_interrupt_vector(Some(4));
+
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(keyboard.clone()), 0x4000, 0x4001, true)
+            .unwrap();
+
+        #[rustfmt::skip]
+        let program = [
+            instructions::MOV_LIT_MEM, 0x02, 0x00, 0x40, 0x00, // mov $0200, &4000
+            instructions::INC_REG, 2,                          // inc r1
+            instructions::JMP_NOT_EQ, 0x00, 0x01, 0x00, 0x05,  // jne $1, &0005
+        ];
+        #[rustfmt::skip]
+        let handler = [
+            instructions::MOV_MEM_REG, 0x40, 0x00, 1, // mov &4000, acc
+            instructions::HLT,
+        ];
+        memory_mapper
+            .load_image(&program, 0x0000, "program")
+            .unwrap();
+        memory_mapper
+            .load_image(&handler, 0x2000, "handler")
+            .unwrap();
+        memory_mapper.set_u16(0x1008, 0x2000).unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        for _ in 0..11 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.get_register("ip"), Ok(0x0005));
+
+        keyboard.borrow_mut().source_mut().push(b"x");
+        cpu.run().unwrap();
+
+        // Status then data, which pops the key
+        assert_eq!(cpu.get_register("acc"), Ok(0x0378));
+        assert_eq!(keyboard.borrow().irq_pending(), None);
+    }
+}
@@ -1,38 +1,147 @@
-use crate::virtual_machine::device::Device;
+use crate::virtual_machine::{device::Device, error::VmError};
+use std::ops::RangeInclusive;
 
 pub struct Memory {
     memory: Box<[u8]>,
+    read_only: Vec<RangeInclusive<usize>>,
 }
 
 impl Memory {
     pub fn new(size: usize) -> Memory {
         Memory {
             memory: vec![0; size].into_boxed_slice(),
+            read_only: Vec::new(),
+        }
+    }
+
+    /// Creates a memory initialized with the given image. Its size is the length of the image.
+    pub fn from_bytes(bytes: &[u8]) -> Memory {
+        Memory {
+            memory: bytes.to_vec().into_boxed_slice(),
+            read_only: Vec::new(),
+        }
+    }
+
+    /// Returns the size of the memory in bytes.
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Write-protects the addresses from start to end, inclusively.
+    pub fn set_read_only(&mut self, start: usize, end: usize) {
+        self.read_only.push(start..=end);
+    }
+
+    /// Removes the write protection from every address.
+    pub fn clear_read_only(&mut self) {
+        self.read_only.clear();
+    }
+
+    /// Returns true if the given address is write-protected.
+    pub fn is_read_only(&self, address: usize) -> bool {
+        self.read_only.iter().any(|range| range.contains(&address))
+    }
+
+    /// Checks that the given number of bytes at the given address can be accessed.
+    fn check_bounds(&self, address: usize, length: usize) -> Result<(), VmError> {
+        match address.checked_add(length) {
+            Some(end) if end <= self.memory.len() => Ok(()),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    /// Checks that the given number of bytes at the given address can be written.
+    fn check_writable(&self, address: usize, length: usize) -> Result<(), VmError> {
+        self.check_bounds(address, length)?;
+
+        match (address..address + length).find(|address| self.is_read_only(*address)) {
+            Some(address) => Err(VmError::ReadOnly { address }),
+            None => Ok(()),
         }
     }
 }
 
 impl Device for Memory {
-    fn get_u16(&self, address: usize) -> Result<u16, String> {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.check_bounds(address, 2)?;
+
         Ok(u16::from_be_bytes([
             self.memory[address],
             self.memory[address + 1],
         ]))
     }
 
-    fn get_u8(&self, address: usize) -> Result<u8, String> {
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.check_bounds(address, 1)?;
+
         Ok(self.memory[address])
     }
 
-    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), String> {
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.check_writable(address, 2)?;
+
         let value = value.to_be_bytes();
         self.memory[address] = value[0];
         self.memory[address + 1] = value[1];
         Ok(())
     }
 
-    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), String> {
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.check_writable(address, 1)?;
+
         self.memory[address] = value;
         Ok(())
     }
+
+    fn check_write(&self, address: usize) -> Result<(), VmError> {
+        self.check_writable(address, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_test() {
+        let mut memory = Memory::new(4);
+
+        assert_eq!(memory.set_u16(2, 0x1234), Ok(()));
+        assert_eq!(memory.get_u16(2), Ok(0x1234));
+        assert_eq!(memory.get_u8(4), Err(VmError::OutOfBounds { address: 4 }));
+        assert_eq!(memory.get_u16(3), Err(VmError::OutOfBounds { address: 3 }));
+        assert_eq!(
+            memory.set_u16(3, 0xFFFF),
+            Err(VmError::OutOfBounds { address: 3 })
+        );
+        assert_eq!(memory.get_u8(3), Ok(0x34));
+    }
+
+    #[test]
+    fn from_bytes_test() {
+        let mut memory = Memory::from_bytes(&[0x12, 0x34, 0x56]);
+
+        assert_eq!(memory.size(), 3);
+        assert_eq!(memory.get_u16(1), Ok(0x3456));
+    }
+
+    #[test]
+    fn read_only_test() {
+        let mut memory = Memory::from_bytes(&[0; 8]);
+        memory.set_read_only(2, 3);
+
+        assert_eq!(memory.set_u8(1, 0x01), Ok(()));
+        assert_eq!(
+            memory.set_u8(3, 0x01),
+            Err(VmError::ReadOnly { address: 3 })
+        );
+        assert_eq!(
+            memory.set_u16(1, 0xFFFF),
+            Err(VmError::ReadOnly { address: 2 })
+        );
+        assert_eq!(memory.get_u16(1), Ok(0x0100));
+
+        memory.clear_read_only();
+        assert_eq!(memory.set_u8(3, 0x01), Ok(()));
+    }
 }
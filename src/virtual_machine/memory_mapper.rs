@@ -1,90 +1,1112 @@
-use crate::virtual_machine::device::Device;
+use crate::virtual_machine::{
+    device::Device, error::VmError, interrupts::INTERRUPT_VECTOR_COUNT, memory::Memory,
+};
+use std::{fmt, mem};
+
+/// Size in bytes of the pages used to dispatch accesses.
+pub const PAGE_SIZE: usize = 0x100;
+
+/// Number of pages covering the 16-bit address space.
+const PAGE_COUNT: usize = 0x10000 / PAGE_SIZE;
+
+/// The device of a region. Plain memory is kept apart to avoid dynamic dispatch.
+enum RegionDevice {
+    Memory(Memory),
+    Device(Box<dyn Device>),
+}
+
+impl RegionDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        match self {
+            RegionDevice::Memory(memory) => memory.get_u16(address),
+            RegionDevice::Device(device) => device.get_u16(address),
+        }
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        match self {
+            RegionDevice::Memory(memory) => memory.get_u8(address),
+            RegionDevice::Device(device) => device.get_u8(address),
+        }
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        match self {
+            RegionDevice::Memory(memory) => memory.set_u16(address, value),
+            RegionDevice::Device(device) => device.set_u16(address, value),
+        }
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        match self {
+            RegionDevice::Memory(memory) => memory.set_u8(address, value),
+            RegionDevice::Device(device) => device.set_u8(address, value),
+        }
+    }
+
+    fn check_write(&self, address: usize) -> Result<(), VmError> {
+        match self {
+            RegionDevice::Memory(memory) => memory.check_write(address),
+            RegionDevice::Device(device) => device.check_write(address),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            RegionDevice::Memory(memory) => memory.reset(),
+            RegionDevice::Device(device) => device.reset(),
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        match self {
+            RegionDevice::Memory(memory) => memory.tick(cycles),
+            RegionDevice::Device(device) => device.tick(cycles),
+        }
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        match self {
+            RegionDevice::Memory(memory) => memory.irq_pending(),
+            RegionDevice::Device(device) => device.irq_pending(),
+        }
+    }
+
+    fn wants_bus(&self) -> bool {
+        match self {
+            RegionDevice::Memory(memory) => memory.wants_bus(),
+            RegionDevice::Device(device) => device.wants_bus(),
+        }
+    }
+
+    fn master_bus(&mut self, bus: &mut Bus) {
+        match self {
+            RegionDevice::Memory(memory) => memory.master_bus(bus),
+            RegionDevice::Device(device) => device.master_bus(bus),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            RegionDevice::Memory(memory) => memory.name(),
+            RegionDevice::Device(device) => device.name(),
+        }
+    }
+
+    fn device_type(&self) -> &'static str {
+        match self {
+            RegionDevice::Memory(memory) => memory.device_type(),
+            RegionDevice::Device(device) => device.device_type(),
+        }
+    }
+
+    fn into_device(self) -> Box<dyn Device> {
+        match self {
+            RegionDevice::Memory(memory) => Box::new(memory),
+            RegionDevice::Device(device) => device,
+        }
+    }
+}
+
+/// How the addresses of a page are dispatched.
+#[derive(Clone, Debug, PartialEq)]
+enum Page {
+    /// No region handles any address of the page.
+    Unmapped,
+    /// The region at the given index handles every address of the page.
+    Region(usize),
+    /// Several regions share the page, so each address must be looked up
+    /// among the regions at the given indexes, in priority order.
+    Mixed(Vec<usize>),
+}
 
 struct Region {
-    device: Box<dyn Device>,
+    handle: RegionHandle,
+    name: String,
+    device: RegionDevice,
     start: usize,
     end: usize,
     remap: bool,
+    wait_states: u64,
+}
+
+impl Region {
+    /// Converts the given address to an address of the device.
+    fn device_address(&self, address: usize) -> usize {
+        if self.remap {
+            address - self.start
+        } else {
+            address
+        }
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        address >= self.start && address <= self.end
+    }
+
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        start <= self.end && end >= self.start
+    }
+
+    fn info(&self) -> RegionInfo {
+        RegionInfo {
+            handle: self.handle,
+            name: self.name.clone(),
+            start: self.start,
+            end: self.end,
+            remap: self.remap,
+            device_type: self.device.device_type(),
+        }
+    }
+}
+
+/// Identifies a mapped region so that it can be unmapped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RegionHandle(usize);
+
+/// Describes a mapped region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionInfo {
+    pub handle: RegionHandle,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    pub remap: bool,
+    pub device_type: &'static str,
+}
+
+/// What `MemoryMapper::map` does when a new region overlaps an existing one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverlapPolicy {
+    /// The new region silently shadows the existing ones.
+    #[default]
+    Allow,
+    /// The new region shadows the existing ones and an `OverlapWarning` is recorded.
+    Warn,
+    /// The mapping fails with `VmError::Overlap`.
+    Reject,
+}
+
+/// A region mapped under `OverlapPolicy::Warn` that shadows part of an existing one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverlapWarning {
+    pub region: RegionInfo,
+    pub shadowed: RegionInfo,
+}
+
+impl fmt::Display for OverlapWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Region '{}' ({:#06X}-{:#06X}) shadows region '{}' ({:#06X}-{:#06X})",
+            self.region.name,
+            self.region.start,
+            self.region.end,
+            self.shadowed.name,
+            self.shadowed.start,
+            self.shadowed.end
+        )
+    }
+}
+
+/// A block of bytes written by `MemoryMapper::load_image`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The view of the memory given to a device mastering the bus.
+pub struct Bus<'a> {
+    memory: &'a mut MemoryMapper,
+    /// The index of the region of the device holding the bus.
+    master: usize,
+}
+
+impl Bus<'_> {
+    pub fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.check_conflict(address)?;
+        self.check_conflict(address + 1)?;
+        self.memory.get_u16(address)
+    }
+
+    pub fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.check_conflict(address)?;
+        self.memory.get_u8(address)
+    }
+
+    pub fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.check_conflict(address)?;
+        self.check_conflict(address + 1)?;
+        self.memory.set_u16(address, value)
+    }
+
+    pub fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.check_conflict(address)?;
+        self.memory.set_u8(address, value)
+    }
+
+    /// Fails if the address belongs to the device holding the bus, which is taken out of its region.
+    fn check_conflict(&self, address: usize) -> Result<(), VmError> {
+        match self.memory.find_region_index(address) {
+            Ok(index) if index == self.master => Err(VmError::BusConflict { address }),
+            _ => Ok(()),
+        }
+    }
 }
 
 pub struct MemoryMapper {
     regions: Vec<Region>,
+    pages: Vec<Page>,
+    segments: Vec<Segment>,
+    overlap_policy: OverlapPolicy,
+    warnings: Vec<OverlapWarning>,
+    alignment_fault: bool,
+    next_handle: usize,
+    wait_cycles: u64,
+    /// The pages watched for writes because they hold decoded instructions.
+    code_pages: Vec<bool>,
+    invalidated_pages: Vec<usize>,
+}
+
+impl Default for MemoryMapper {
+    fn default() -> MemoryMapper {
+        MemoryMapper::new()
+    }
 }
 
 impl MemoryMapper {
     pub fn new() -> MemoryMapper {
         MemoryMapper {
             regions: Vec::new(),
+            pages: vec![Page::Unmapped; PAGE_COUNT],
+            segments: Vec::new(),
+            overlap_policy: OverlapPolicy::Allow,
+            warnings: Vec::new(),
+            alignment_fault: false,
+            next_handle: 0,
+            wait_cycles: 0,
+            code_pages: vec![false; PAGE_COUNT],
+            invalidated_pages: Vec::new(),
         }
     }
 
-    /// Adds the given mapping to the list of regions.
-    pub fn map(&mut self, device: Box<dyn Device>, start: usize, end: usize, remap: bool) {
+    /// Sets what happens when a new mapping overlaps an existing region.
+    pub fn set_overlap_policy(&mut self, overlap_policy: OverlapPolicy) {
+        self.overlap_policy = overlap_policy;
+    }
+
+    /// Returns the overlaps recorded under `OverlapPolicy::Warn`, oldest first.
+    pub fn warnings(&self) -> &[OverlapWarning] {
+        &self.warnings
+    }
+
+    /// Returns the recorded overlaps and forgets them.
+    pub fn take_warnings(&mut self) -> Vec<OverlapWarning> {
+        mem::take(&mut self.warnings)
+    }
+
+    /// Adds the given mapping to the list of regions, named after the device.
+    /// Later mappings take precedence over earlier ones.
+    pub fn map(
+        &mut self,
+        device: Box<dyn Device>,
+        start: usize,
+        end: usize,
+        remap: bool,
+    ) -> Result<RegionHandle, VmError> {
+        let name = String::from(device.name());
+        self.map_named(&name, device, start, end, remap)
+    }
+
+    /// Adds the given mapping to the list of regions under the given name.
+    /// Later mappings take precedence over earlier ones.
+    pub fn map_named(
+        &mut self,
+        name: &str,
+        device: Box<dyn Device>,
+        start: usize,
+        end: usize,
+        remap: bool,
+    ) -> Result<RegionHandle, VmError> {
+        self.insert_region(name, RegionDevice::Device(device), start, end, remap)
+    }
+
+    /// Adds the given memory to the list of regions, named after the device.
+    /// Accesses to the memory are dispatched without going through the `Device` trait object.
+    pub fn map_memory(
+        &mut self,
+        memory: Memory,
+        start: usize,
+        end: usize,
+        remap: bool,
+    ) -> Result<RegionHandle, VmError> {
+        let name = String::from(memory.name());
+        self.map_memory_named(&name, memory, start, end, remap)
+    }
+
+    /// Adds the given memory to the list of regions under the given name.
+    /// Accesses to the memory are dispatched without going through the `Device` trait object.
+    pub fn map_memory_named(
+        &mut self,
+        name: &str,
+        memory: Memory,
+        start: usize,
+        end: usize,
+        remap: bool,
+    ) -> Result<RegionHandle, VmError> {
+        self.insert_region(name, RegionDevice::Memory(memory), start, end, remap)
+    }
+
+    /// Adds the given region in front of the existing ones.
+    fn insert_region(
+        &mut self,
+        name: &str,
+        device: RegionDevice,
+        start: usize,
+        end: usize,
+        remap: bool,
+    ) -> Result<RegionHandle, VmError> {
+        let mut shadowed = None;
+        if let Some(existing) = self
+            .regions
+            .iter()
+            .find(|region| region.overlaps(start, end))
+        {
+            match self.overlap_policy {
+                OverlapPolicy::Allow => {}
+                OverlapPolicy::Warn => shadowed = Some(existing.info()),
+                OverlapPolicy::Reject => {
+                    return Err(VmError::Overlap {
+                        start,
+                        end,
+                        name: existing.name.clone(),
+                    })
+                }
+            }
+        }
+
+        let handle = RegionHandle(self.next_handle);
+        self.next_handle += 1;
+
         let region = Region {
+            handle,
+            name: String::from(name),
             device,
             start,
             end,
             remap,
+            wait_states: 0,
         };
 
+        if let Some(shadowed) = shadowed {
+            self.warnings.push(OverlapWarning {
+                region: region.info(),
+                shadowed,
+            });
+        }
+
         self.regions.insert(0, region);
+        self.rebuild_pages();
+        Ok(handle)
     }
 
-    /// Finds the corresponding region for the given address.
-    fn find_region(&mut self, address: usize) -> Result<&mut Region, String> {
+    /// Recomputes which region handles each page.
+    fn rebuild_pages(&mut self) {
+        self.invalidate_code_pages();
+
+        for (page_index, page) in self.pages.iter_mut().enumerate() {
+            let page_start = page_index * PAGE_SIZE;
+            let page_end = page_start + PAGE_SIZE - 1;
+
+            let overlapping: Vec<usize> = self
+                .regions
+                .iter()
+                .enumerate()
+                .filter(|(_, region)| region.overlaps(page_start, page_end))
+                .map(|(index, _)| index)
+                .collect();
+
+            // The first overlapping region shadows all the others
+            *page = match overlapping.first() {
+                Some(&index)
+                    if self.regions[index].contains(page_start)
+                        && self.regions[index].contains(page_end) =>
+                {
+                    Page::Region(index)
+                }
+                Some(_) => Page::Mixed(overlapping),
+                None => Page::Unmapped,
+            };
+        }
+    }
+
+    /// Removes the region with the given handle and returns its device.
+    pub fn unmap(&mut self, handle: RegionHandle) -> Result<Box<dyn Device>, VmError> {
+        let index = self
+            .regions
+            .iter()
+            .position(|region| region.handle == handle)
+            .ok_or(VmError::UnknownRegion)?;
+
+        let region = self.regions.remove(index);
+        self.rebuild_pages();
+        Ok(region.device.into_device())
+    }
+
+    /// Removes the most recently mapped region with the given name and returns its device.
+    pub fn unmap_named(&mut self, name: &str) -> Result<Box<dyn Device>, VmError> {
+        let handle = self
+            .region_named(name)
+            .ok_or(VmError::UnknownRegion)?
+            .handle;
+        self.unmap(handle)
+    }
+
+    /// Returns a description of every region, from highest to lowest precedence.
+    pub fn regions(&self) -> Vec<RegionInfo> {
+        self.regions.iter().map(Region::info).collect()
+    }
+
+    /// Returns the most recently mapped region with the given name.
+    pub fn region_named(&self, name: &str) -> Option<RegionInfo> {
+        self.regions
+            .iter()
+            .find(|region| region.name == name)
+            .map(Region::info)
+    }
+
+    /// Returns the region that handles the given address.
+    pub fn region_at(&self, address: usize) -> Option<RegionInfo> {
+        self.regions
+            .iter()
+            .find(|region| region.contains(address))
+            .map(Region::info)
+    }
+
+    /// Sets the number of cycles every access to the region with the given handle stalls the CPU.
+    pub fn set_wait_states(
+        &mut self,
+        handle: RegionHandle,
+        wait_states: u64,
+    ) -> Result<(), VmError> {
         let region = self
             .regions
             .iter_mut()
-            .find(|region| address >= region.start && address <= region.end);
+            .find(|region| region.handle == handle)
+            .ok_or(VmError::UnknownRegion)?;
 
-        region.ok_or_else(|| format!("No memory region found for address {}", address))
+        region.wait_states = wait_states;
+        self.invalidate_code_pages();
+        Ok(())
     }
 
-    /// Returns the u16 value at the given address.
-    pub fn get_u16(&mut self, address: usize) -> Result<u16, String> {
-        let region = self.find_region(address)?;
-        let address = if region.remap {
-            address - region.start
+    /// Returns true if the page of the given address is handled by plain memory without wait states.
+    /// The contents of such a page only change when written through the mapper.
+    pub fn is_plain_memory(&self, address: usize) -> bool {
+        match self.pages.get(address / PAGE_SIZE) {
+            Some(Page::Region(index)) => {
+                let region = &self.regions[*index];
+                region.wait_states == 0 && matches!(region.device, RegionDevice::Memory(_))
+            }
+            _ => false,
+        }
+    }
+
+    /// Reports the next write to the page of the given address through `take_invalidated_pages`.
+    pub fn watch_code_page(&mut self, address: usize) {
+        if let Some(code_page) = self.code_pages.get_mut(address / PAGE_SIZE) {
+            *code_page = true;
+        }
+    }
+
+    /// Returns the indexes of the watched pages written since the last call. They are no longer watched.
+    pub fn take_invalidated_pages(&mut self) -> Vec<usize> {
+        mem::take(&mut self.invalidated_pages)
+    }
+
+    /// Returns true if a watched page was written since the last call to `take_invalidated_pages`.
+    pub fn has_invalidated_pages(&self) -> bool {
+        !self.invalidated_pages.is_empty()
+    }
+
+    /// Marks the watched page of the given address as written.
+    fn invalidate_code_page(&mut self, address: usize) {
+        let page = address / PAGE_SIZE;
+        if let Some(code_page @ true) = self.code_pages.get_mut(page) {
+            *code_page = false;
+            self.invalidated_pages.push(page);
+        }
+    }
+
+    /// Marks every watched page as written, since the mapping has changed.
+    fn invalidate_code_pages(&mut self) {
+        for (page, code_page) in self.code_pages.iter_mut().enumerate() {
+            if *code_page {
+                *code_page = false;
+                self.invalidated_pages.push(page);
+            }
+        }
+    }
+
+    /// Returns the wait states accumulated by the accesses since the last call and resets them.
+    pub fn take_wait_cycles(&mut self) -> u64 {
+        mem::replace(&mut self.wait_cycles, 0)
+    }
+
+    /// Advances every mapped device by the given number of CPU cycles.
+    /// Devices requesting the bus access the memory right after their tick.
+    pub fn tick(&mut self, cycles: u64) {
+        for index in 0..self.regions.len() {
+            self.regions[index].device.tick(cycles);
+
+            if self.regions[index].device.wants_bus() {
+                self.master_bus(index);
+            }
+        }
+    }
+
+    /// Lets the device of the region at the given index access the memory.
+    fn master_bus(&mut self, index: usize) {
+        // The device is taken out of its region for as long as it holds the bus
+        let placeholder = RegionDevice::Memory(Memory::new(0));
+        let mut device = mem::replace(&mut self.regions[index].device, placeholder);
+        // The accesses of the device do not stall the CPU
+        let wait_cycles = self.wait_cycles;
+
+        device.master_bus(&mut Bus {
+            memory: self,
+            master: index,
+        });
+        self.regions[index].device = device;
+        self.wait_cycles = wait_cycles;
+    }
+
+    /// Resets every mapped device.
+    pub fn reset(&mut self) {
+        for region in self.regions.iter_mut() {
+            region.device.reset();
+        }
+    }
+
+    /// Returns the mask of the interrupt vectors requested by the mapped devices.
+    pub fn irq_pending(&self) -> u16 {
+        self.regions
+            .iter()
+            .filter_map(|region| region.device.irq_pending())
+            .fold(0, |mask, vector| {
+                mask | 1 << (vector % INTERRUPT_VECTOR_COUNT)
+            })
+    }
+
+    /// Returns the name of the device handling the given address.
+    pub fn device_name(&self, address: usize) -> Option<&str> {
+        self.regions
+            .iter()
+            .find(|region| region.contains(address))
+            .map(|region| region.device.name())
+    }
+
+    /// Writes the given image at the given address and records it as a segment with the given name.
+    /// The image may span several regions, but every byte of it must be mapped and writable.
+    /// Nothing is written if a byte is not.
+    pub fn load_image(&mut self, bytes: &[u8], address: usize, name: &str) -> Result<(), VmError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        let end = address + bytes.len() - 1;
+        for byte_address in address..=end {
+            let region = self.find_region(byte_address)?;
+            region
+                .device
+                .check_write(region.device_address(byte_address))?;
+        }
+
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.set_u8(address + offset, *byte)?;
+        }
+
+        self.segments.push(Segment {
+            name: String::from(name),
+            start: address,
+            end,
+        });
+
+        Ok(())
+    }
+
+    /// Returns the segments written by `load_image`, in loading order.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Makes u16 accesses whose bytes belong to different regions fail with
+    /// `VmError::Unaligned` instead of being split into two u8 accesses.
+    pub fn set_alignment_fault(&mut self, alignment_fault: bool) {
+        self.alignment_fault = alignment_fault;
+    }
+
+    /// Finds the index of the corresponding region for the given address.
+    fn find_region_index(&self, address: usize) -> Result<usize, VmError> {
+        match self.pages.get(address / PAGE_SIZE) {
+            Some(Page::Region(index)) => Ok(*index),
+            Some(Page::Unmapped) => Err(VmError::Unmapped { address }),
+            Some(Page::Mixed(indexes)) => indexes
+                .iter()
+                .copied()
+                .find(|&index| self.regions[index].contains(address))
+                .ok_or(VmError::Unmapped { address }),
+            // Past the page table, only the regions themselves can tell
+            None => self
+                .regions
+                .iter()
+                .position(|region| region.contains(address))
+                .ok_or(VmError::Unmapped { address }),
+        }
+    }
+
+    /// Finds the corresponding region for the given address.
+    fn find_region(&mut self, address: usize) -> Result<&mut Region, VmError> {
+        let index = self.find_region_index(address)?;
+        Ok(&mut self.regions[index])
+    }
+
+    /// Finds the region holding both bytes of the u16 at the given address.
+    /// Returns None if the bytes belong to different regions and the access must be split.
+    fn find_u16_region(&mut self, address: usize) -> Result<Option<&mut Region>, VmError> {
+        let index = self.find_region_index(address)?;
+        let next_index = self.find_region_index(address + 1)?;
+
+        if index == next_index {
+            Ok(Some(&mut self.regions[index]))
+        } else if self.alignment_fault {
+            Err(VmError::Unaligned { address })
         } else {
-            address
-        };
+            Ok(None)
+        }
+    }
 
-        region.device.get_u16(address)
+    /// Returns the u16 value at the given address.
+    pub fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        match self.find_u16_region(address)? {
+            Some(region) => {
+                let address = region.device_address(address);
+                let wait_states = region.wait_states;
+                let value = region.device.get_u16(address);
+
+                self.wait_cycles += wait_states;
+                value
+            }
+            None => Ok(u16::from_be_bytes([
+                self.get_u8(address)?,
+                self.get_u8(address + 1)?,
+            ])),
+        }
     }
 
     /// Returns the u8 value at the given address.
-    pub fn get_u8(&mut self, address: usize) -> Result<u8, String> {
+    pub fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
         let region = self.find_region(address)?;
-        let address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+        let address = region.device_address(address);
+        let wait_states = region.wait_states;
+        let value = region.device.get_u8(address);
 
-        region.device.get_u8(address)
+        self.wait_cycles += wait_states;
+        value
     }
 
     /// Sets the given u16 value at the given address.
-    pub fn set_u16(&mut self, address: usize, value: u16) -> Result<(), String> {
-        let region = self.find_region(address)?;
-        let address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+    pub fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.invalidate_code_page(address);
+        self.invalidate_code_page(address + 1);
+
+        match self.find_u16_region(address)? {
+            Some(region) => {
+                let address = region.device_address(address);
+                let wait_states = region.wait_states;
+                let result = region.device.set_u16(address, value);
+
+                self.wait_cycles += wait_states;
+                result
+            }
+            None => {
+                // Both bytes are checked first so that a failure writes neither of them
+                for byte_address in address..=address + 1 {
+                    let region = self.find_region(byte_address)?;
+                    region
+                        .device
+                        .check_write(region.device_address(byte_address))?;
+                }
 
-        region.device.set_u16(address, value)
+                let [high, low] = value.to_be_bytes();
+                self.set_u8(address, high)?;
+                self.set_u8(address + 1, low)
+            }
+        }
     }
 
-    /// Sets the given u16 value at the given address.
-    pub fn set_u8(&mut self, address: usize, value: u8) -> Result<(), String> {
+    /// Sets the given u8 value at the given address.
+    pub fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.invalidate_code_page(address);
+
         let region = self.find_region(address)?;
-        let address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+        let address = region.device_address(address);
+        let wait_states = region.wait_states;
+        let result = region.device.set_u8(address, value);
+
+        self.wait_cycles += wait_states;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        memory::Memory,
+        rom_device::{RomDevice, WritePolicy},
+        screen_device::ScreenDevice,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn load_image_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x00, 0x0F, true)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x10, 0x1F, true)
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.load_image(&[0x12, 0x34, 0x56], 0x0F, "program"),
+            Ok(())
+        );
+        assert_eq!(memory_mapper.get_u8(0x0F), Ok(0x12));
+        assert_eq!(memory_mapper.get_u16(0x10), Ok(0x3456));
+        assert_eq!(
+            memory_mapper.segments(),
+            &[Segment {
+                name: String::from("program"),
+                start: 0x0F,
+                end: 0x11,
+            }]
+        );
+
+        assert_eq!(
+            memory_mapper.load_image(&[0xFF, 0xFF], 0x1F, "data"),
+            Err(VmError::Unmapped { address: 0x20 })
+        );
+        assert_eq!(memory_mapper.get_u8(0x1F), Ok(0x00));
+        assert_eq!(memory_mapper.segments().len(), 1);
+    }
+
+    #[test]
+    fn load_image_rom_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(
+                Box::new(RomDevice::new(&[0xAB; 4], WritePolicy::Reject)),
+                0x00,
+                0x03,
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.load_image(&[0x01], 0x02, "patch"),
+            Err(VmError::ReadOnly { address: 0x02 })
+        );
+        assert_eq!(memory_mapper.get_u8(0x02), Ok(0xAB));
+
+        // The memory before the ROM is left unchanged
+        memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x10, 0x1F, true)
+            .unwrap();
+        let mut memory = Memory::new(0x10);
+        memory.set_read_only(0x0E, 0x0E);
+        memory_mapper
+            .map(Box::new(memory), 0x20, 0x2F, true)
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.load_image(&[0x01; 0x20], 0x10, "program"),
+            Err(VmError::ReadOnly { address: 0x0E })
+        );
+        assert_eq!(memory_mapper.get_u16(0x10), Ok(0x0000));
+        assert_eq!(memory_mapper.get_u8(0x2D), Ok(0x00));
+        assert!(memory_mapper.segments().is_empty());
+    }
+
+    #[test]
+    fn unmap_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(
+                Box::new(Memory::from_bytes(&[0x11; 0x10])),
+                0x00,
+                0x0F,
+                true,
+            )
+            .unwrap();
+        let handle = memory_mapper
+            .map_named(
+                "patch",
+                Box::new(Memory::from_bytes(&[0x22; 4])),
+                0x04,
+                0x07,
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(memory_mapper.get_u8(0x04), Ok(0x22));
+        assert!(memory_mapper.unmap(handle).is_ok());
+        assert_eq!(memory_mapper.get_u8(0x04), Ok(0x11));
+        assert!(memory_mapper.unmap(handle).is_err());
+        assert_eq!(memory_mapper.regions().len(), 1);
+    }
+
+    #[test]
+    fn overlap_policy_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper.set_overlap_policy(OverlapPolicy::Reject);
+        memory_mapper
+            .map_named("ram", Box::new(Memory::new(0x10)), 0x00, 0x0F, true)
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.map_named("screen", Box::new(Memory::new(0x10)), 0x0F, 0x1E, true),
+            Err(VmError::Overlap {
+                start: 0x0F,
+                end: 0x1E,
+                name: String::from("ram")
+            })
+        );
+        assert!(memory_mapper
+            .map_named("screen", Box::new(Memory::new(0x10)), 0x10, 0x1F, true)
+            .is_ok());
+    }
+
+    #[test]
+    fn overlap_warning_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper.set_overlap_policy(OverlapPolicy::Warn);
+        let ram = memory_mapper
+            .map_named("ram", Box::new(Memory::new(0x10)), 0x00, 0x0F, true)
+            .unwrap();
+        let screen = memory_mapper
+            .map_named("screen", Box::new(Memory::new(0x10)), 0x08, 0x17, true)
+            .unwrap();
+
+        let warnings = memory_mapper.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].region.handle, screen);
+        assert_eq!(warnings[0].shadowed.handle, ram);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Region 'screen' (0x0008-0x0017) shadows region 'ram' (0x0000-0x000F)"
+        );
+        assert!(memory_mapper.warnings().is_empty());
+    }
+
+    #[test]
+    fn regions_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        let ram = memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x00, 0x0F, false)
+            .unwrap();
+        let rom = memory_mapper
+            .map_named(
+                "boot",
+                Box::new(RomDevice::new(&[0; 0x10], WritePolicy::Ignore)),
+                0x10,
+                0x1F,
+                true,
+            )
+            .unwrap();
+
+        let regions = memory_mapper.regions();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].handle, rom);
+        assert_eq!(regions[0].name, "boot");
+        assert_eq!(regions[0].start, 0x10);
+        assert_eq!(regions[0].end, 0x1F);
+        assert!(regions[0].remap);
+        assert!(regions[0].device_type.ends_with("RomDevice"));
+        assert_eq!(regions[1].handle, ram);
+        assert_eq!(regions[1].name, "Memory");
+        assert_eq!(memory_mapper.device_name(0x1F), Some("RomDevice"));
+
+        assert_eq!(memory_mapper.region_named("boot"), Some(regions[0].clone()));
+        assert_eq!(memory_mapper.region_at(0x0F), Some(regions[1].clone()));
+        assert_eq!(memory_mapper.region_at(0x20), None);
+        assert!(memory_mapper.unmap_named("boot").is_ok());
+        assert_eq!(memory_mapper.region_named("boot"), None);
+    }
+
+    #[test]
+    fn u16_across_regions_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(ScreenDevice::new()), 0x3000, 0x30FF, true)
+            .unwrap();
 
-        region.device.set_u8(address, value)
+        memory_mapper.set_u8(0x2FFF, 0x12).unwrap();
+        memory_mapper.set_u8(0x3100, 0x34).unwrap();
+
+        // The screen always reads as zero
+        assert_eq!(memory_mapper.get_u16(0x2FFF), Ok(0x1200));
+        assert_eq!(memory_mapper.get_u16(0x30FF), Ok(0x0034));
+
+        assert_eq!(memory_mapper.set_u16(0x2FFF, 0x5641), Ok(()));
+        assert_eq!(memory_mapper.get_u8(0x2FFF), Ok(0x56));
+        assert_eq!(memory_mapper.get_u8(0x3000), Ok(0x00));
+
+        memory_mapper.set_alignment_fault(true);
+        assert_eq!(
+            memory_mapper.get_u16(0x2FFF),
+            Err(VmError::Unaligned { address: 0x2FFF })
+        );
+        assert_eq!(
+            memory_mapper.set_u16(0x30FF, 0x0000),
+            Err(VmError::Unaligned { address: 0x30FF })
+        );
+        assert_eq!(memory_mapper.get_u16(0x2FFE), Ok(0x0056));
+    }
+
+    #[test]
+    fn u16_past_region_end_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x00, 0x0F, true)
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.get_u16(0x0F),
+            Err(VmError::Unmapped { address: 0x10 })
+        );
+        assert_eq!(
+            memory_mapper.set_u16(0x0F, 0x1234),
+            Err(VmError::Unmapped { address: 0x10 })
+        );
+    }
+
+    #[test]
+    fn u16_into_read_only_region_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x00, 0x0F, true)
+            .unwrap();
+        memory_mapper
+            .map(
+                Box::new(RomDevice::new(&[0xAB; 0x10], WritePolicy::Reject)),
+                0x10,
+                0x1F,
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.set_u16(0x0F, 0x1234),
+            Err(VmError::ReadOnly { address: 0x00 })
+        );
+        assert_eq!(memory_mapper.get_u16(0x0F), Ok(0x00AB));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn page_dispatch_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::from_bytes(&[0x11; 0x10000]), 0x0000, 0xFFFF, false)
+            .unwrap();
+        let handle = memory_mapper
+            .map(
+                Box::new(Memory::from_bytes(&[0x22; 0x180])),
+                0x1000,
+                0x117F,
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(memory_mapper.pages[0x0F], Page::Region(1));
+        assert_eq!(memory_mapper.pages[0x10], Page::Region(0));
+        assert_eq!(memory_mapper.pages[0x11], Page::Mixed(vec![0, 1]));
+        assert_eq!(memory_mapper.get_u8(0x0FFF), Ok(0x11));
+        assert_eq!(memory_mapper.get_u8(0x1000), Ok(0x22));
+        assert_eq!(memory_mapper.get_u8(0x117F), Ok(0x22));
+        assert_eq!(memory_mapper.get_u8(0x1180), Ok(0x11));
+
+        memory_mapper.unmap(handle).unwrap();
+        assert_eq!(memory_mapper.pages[0x11], Page::Region(0));
+        assert_eq!(memory_mapper.get_u8(0x1000), Ok(0x11));
+    }
+
+    /// Reads the given addresses through the bus on every tick.
+    struct BusProbe {
+        addresses: Vec<usize>,
+        results: Vec<Result<u8, VmError>>,
+    }
+
+    impl Device for BusProbe {
+        fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+            Err(VmError::OutOfBounds { address })
+        }
+
+        fn get_u8(&mut self, _address: usize) -> Result<u8, VmError> {
+            Ok(0x33)
+        }
+
+        fn set_u16(&mut self, address: usize, _value: u16) -> Result<(), VmError> {
+            Err(VmError::OutOfBounds { address })
+        }
+
+        fn set_u8(&mut self, address: usize, _value: u8) -> Result<(), VmError> {
+            Err(VmError::OutOfBounds { address })
+        }
+
+        fn wants_bus(&self) -> bool {
+            true
+        }
+
+        fn master_bus(&mut self, bus: &mut Bus) {
+            self.results = self
+                .addresses
+                .iter()
+                .map(|&address| bus.get_u8(address))
+                .collect();
+        }
+    }
+
+    #[test]
+    fn bus_conflict_test() {
+        let probe = Rc::new(RefCell::new(BusProbe {
+            addresses: vec![0x3FFF, 0x4000, 0x4001],
+            results: Vec::new(),
+        }));
+
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::from_bytes(&[0x11; 0x10000]), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(probe.clone()), 0x4000, 0x4001, true)
+            .unwrap();
+        memory_mapper.tick(1);
+
+        assert_eq!(
+            probe.borrow().results,
+            vec![
+                Ok(0x11),
+                Err(VmError::BusConflict { address: 0x4000 }),
+                Err(VmError::BusConflict { address: 0x4001 }),
+            ]
+        );
+        assert_eq!(memory_mapper.get_u8(0x4000), Ok(0x33));
+    }
+}
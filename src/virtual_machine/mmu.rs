@@ -0,0 +1,209 @@
+use crate::virtual_machine::{error::VmError, memory_mapper::MemoryMapper};
+use std::fmt;
+
+/// Size in bytes of the pages translated by the MMU.
+pub const MMU_PAGE_SIZE: usize = 0x100;
+
+/// Number of entries of a page table, covering the 16-bit address space.
+pub const PAGE_TABLE_ENTRY_COUNT: usize = 0x10000 / MMU_PAGE_SIZE;
+
+/// Page flag allowing reads.
+pub const PAGE_READ: u8 = 0x01;
+/// Page flag allowing writes.
+pub const PAGE_WRITE: u8 = 0x02;
+/// Page flag allowing instruction fetches.
+pub const PAGE_EXECUTE: u8 = 0x04;
+/// Page flag allowing accesses in user mode.
+pub const PAGE_USER: u8 = 0x08;
+
+/// The privilege level of the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Every instruction is allowed and pages without `PAGE_USER` are accessible.
+    Supervisor,
+    /// Privileged instructions and writes to `im` raise a general protection fault.
+    User,
+}
+
+/// The kind of a memory access, checked against the page flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    /// Returns the page flag allowing the access.
+    fn flag(self) -> u8 {
+        match self {
+            Access::Read => PAGE_READ,
+            Access::Write => PAGE_WRITE,
+            Access::Execute => PAGE_EXECUTE,
+        }
+    }
+
+    /// Returns the code of the access given to page fault handlers.
+    pub fn code(self) -> u16 {
+        match self {
+            Access::Read => 0,
+            Access::Write => 1,
+            Access::Execute => 2,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// An entry of a page table: the physical page in the high byte and the `PAGE_*` flags in the low byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageTableEntry(pub u16);
+
+impl PageTableEntry {
+    pub fn new(physical_page: u8, flags: u8) -> PageTableEntry {
+        PageTableEntry(u16::from_be_bytes([physical_page, flags]))
+    }
+
+    pub fn physical_page(self) -> u8 {
+        self.0.to_be_bytes()[0]
+    }
+
+    pub fn flags(self) -> u8 {
+        self.0.to_be_bytes()[1]
+    }
+
+    /// Returns whether the page can be accessed in the given way and mode.
+    pub fn allows(self, access: Access, mode: Mode) -> bool {
+        let flags = self.flags();
+        flags & access.flag() != 0 && (mode == Mode::Supervisor || flags & PAGE_USER != 0)
+    }
+}
+
+/// Translates the virtual addresses of the CPU to physical addresses of the memory mapper.
+///
+/// The page table lives in physical memory and is read on every access, so the
+/// guest can change it at any time. While disabled, addresses are physical.
+#[derive(Clone, Debug, Default)]
+pub struct Mmu {
+    page_table_address: Option<u16>,
+}
+
+impl Mmu {
+    pub fn new() -> Mmu {
+        Mmu::default()
+    }
+
+    /// Enables the translation through the page table at the given physical address.
+    pub fn enable(&mut self, page_table_address: u16) {
+        self.page_table_address = Some(page_table_address);
+    }
+
+    pub fn disable(&mut self) {
+        self.page_table_address = None;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.page_table_address.is_some()
+    }
+
+    pub fn page_table_address(&self) -> Option<u16> {
+        self.page_table_address
+    }
+
+    /// Returns the physical address of the given virtual address.
+    /// Fails with `VmError::PageFault` if the page does not allow the access.
+    pub fn translate(
+        &self,
+        memory: &mut MemoryMapper,
+        address: usize,
+        access: Access,
+        mode: Mode,
+    ) -> Result<usize, VmError> {
+        let page_table_address = match self.page_table_address {
+            Some(page_table_address) => usize::from(page_table_address),
+            None => return Ok(address),
+        };
+
+        let page = address / MMU_PAGE_SIZE;
+        if page >= PAGE_TABLE_ENTRY_COUNT {
+            return Err(VmError::PageFault { address, access });
+        }
+
+        let entry = PageTableEntry(memory.get_u16(page_table_address + page * 2)?);
+        if !entry.allows(access, mode) {
+            return Err(VmError::PageFault { address, access });
+        }
+
+        Ok(usize::from(entry.physical_page()) * MMU_PAGE_SIZE + address % MMU_PAGE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::memory::Memory;
+
+    #[test]
+    fn translate_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .set_u16(0x0402, PageTableEntry::new(0x80, PAGE_READ | PAGE_USER).0)
+            .unwrap();
+        memory_mapper
+            .set_u16(0x0404, PageTableEntry::new(0x81, PAGE_READ | PAGE_WRITE).0)
+            .unwrap();
+
+        let mut mmu = Mmu::new();
+        assert_eq!(
+            mmu.translate(&mut memory_mapper, 0x0123, Access::Write, Mode::User),
+            Ok(0x0123)
+        );
+
+        mmu.enable(0x0400);
+        assert_eq!(
+            mmu.translate(&mut memory_mapper, 0x0123, Access::Read, Mode::User),
+            Ok(0x8023)
+        );
+        assert_eq!(
+            mmu.translate(&mut memory_mapper, 0x0123, Access::Write, Mode::Supervisor),
+            Err(VmError::PageFault {
+                address: 0x0123,
+                access: Access::Write
+            })
+        );
+        assert_eq!(
+            mmu.translate(&mut memory_mapper, 0x02FF, Access::Write, Mode::Supervisor),
+            Ok(0x81FF)
+        );
+        assert_eq!(
+            mmu.translate(&mut memory_mapper, 0x0200, Access::Read, Mode::User),
+            Err(VmError::PageFault {
+                address: 0x0200,
+                access: Access::Read
+            })
+        );
+        assert_eq!(
+            mmu.translate(
+                &mut memory_mapper,
+                0x0000,
+                Access::Execute,
+                Mode::Supervisor
+            ),
+            Err(VmError::PageFault {
+                address: 0x0000,
+                access: Access::Execute
+            })
+        );
+    }
+}
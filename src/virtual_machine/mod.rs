@@ -1,6 +1,25 @@
+pub mod audio_device;
+pub mod banked_memory;
+pub mod block_translator;
 pub mod cpu;
 pub mod device;
+pub mod disk_device;
+pub mod dma_device;
+pub mod error;
+pub mod framebuffer_device;
+pub mod host_calls;
+pub mod instruction_cache;
 pub mod instructions;
+pub mod interrupts;
+pub mod keyboard_device;
 pub mod memory;
 pub mod memory_mapper;
-pub mod screen_device;
\ No newline at end of file
+pub mod mmu;
+pub mod registers;
+pub mod rng_device;
+pub mod rom_device;
+pub mod rtc_device;
+pub mod screen_backend;
+pub mod screen_device;
+pub mod timer_device;
+pub mod uart_device;
\ No newline at end of file
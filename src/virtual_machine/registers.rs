@@ -0,0 +1,142 @@
+use crate::virtual_machine::error::VmError;
+use std::{fmt, str::FromStr};
+
+/// Number of registers in the register file.
+pub const REGISTER_COUNT: usize = 13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Register {
+    Ip,
+    Acc,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+    R8,
+    Sp,
+    Fp,
+    Im,
+}
+
+impl Register {
+    /// All registers, ordered by their encoded index.
+    pub const ALL: [Register; REGISTER_COUNT] = [
+        Register::Ip,
+        Register::Acc,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+        Register::R8,
+        Register::Sp,
+        Register::Fp,
+        Register::Im,
+    ];
+
+    /// Returns the register encoded by the given index.
+    pub fn from_index(index: u8) -> Option<Register> {
+        Register::ALL.get(index as usize).copied()
+    }
+
+    /// Returns the index used to encode the register in machine code.
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the lowercase name of the register.
+    pub fn name(self) -> &'static str {
+        match self {
+            Register::Ip => "ip",
+            Register::Acc => "acc",
+            Register::R1 => "r1",
+            Register::R2 => "r2",
+            Register::R3 => "r3",
+            Register::R4 => "r4",
+            Register::R5 => "r5",
+            Register::R6 => "r6",
+            Register::R7 => "r7",
+            Register::R8 => "r8",
+            Register::Sp => "sp",
+            Register::Fp => "fp",
+            Register::Im => "im",
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Register {
+    type Err = VmError;
+
+    fn from_str(name: &str) -> Result<Register, VmError> {
+        Register::ALL
+            .iter()
+            .find(|register| register.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| VmError::UnknownRegister {
+                name: String::from(name),
+            })
+    }
+}
+
+/// The values held by the CPU registers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegisterFile {
+    values: [u16; REGISTER_COUNT],
+}
+
+impl RegisterFile {
+    pub fn new() -> RegisterFile {
+        RegisterFile {
+            values: [0; REGISTER_COUNT],
+        }
+    }
+
+    /// Returns the value in the given register.
+    pub fn get(&self, register: Register) -> u16 {
+        self.values[register as usize]
+    }
+
+    /// Sets the given value to the given register.
+    pub fn set(&mut self, register: Register, value: u16) {
+        self.values[register as usize] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_index_test() {
+        assert_eq!(Register::from_index(0), Some(Register::Ip));
+        assert_eq!(Register::from_index(11), Some(Register::Fp));
+        assert_eq!(Register::from_index(12), Some(Register::Im));
+        assert_eq!(Register::from_index(13), None);
+        for register in Register::ALL.iter() {
+            assert_eq!(Register::from_index(register.index()), Some(*register));
+        }
+    }
+
+    #[test]
+    fn from_str_test() {
+        assert_eq!("acc".parse(), Ok(Register::Acc));
+        assert_eq!("R8".parse(), Ok(Register::R8));
+        assert_eq!(
+            "r9".parse::<Register>(),
+            Err(VmError::UnknownRegister {
+                name: String::from("r9")
+            })
+        );
+    }
+}
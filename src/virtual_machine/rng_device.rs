@@ -0,0 +1,128 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// Data register: every read returns new random bits.
+pub const RNG_DATA: usize = 0x00;
+/// Seed register: writing restarts the sequence from the given seed.
+pub const RNG_SEED: usize = 0x02;
+
+/// A random number generator. The same seed always produces the same sequence.
+#[derive(Clone, Debug)]
+pub struct RngDevice {
+    seed: u64,
+    state: u64,
+}
+
+impl RngDevice {
+    pub fn new(seed: u64) -> RngDevice {
+        RngDevice { seed, state: seed }
+    }
+
+    /// Seeds the generator with the randomness of the host.
+    pub fn from_entropy() -> RngDevice {
+        RngDevice::new(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the next 64 random bits, using SplitMix64.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut value = self.state;
+        value = (value ^ value >> 30).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ value >> 27).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ value >> 31
+    }
+}
+
+impl WordRegisters for RngDevice {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            RNG_DATA => Ok(self.next() as u16),
+            RNG_SEED => Ok(self.seed as u16),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        match address {
+            RNG_DATA => {}
+            RNG_SEED => *self = RngDevice::new(value.into()),
+            _ => return Err(VmError::OutOfBounds { address }),
+        }
+
+        Ok(())
+    }
+}
+
+impl Device for RngDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        // Reading the data register back would advance the sequence
+        if address & !1 == RNG_DATA {
+            return Ok(());
+        }
+
+        self.set_register_u8(address, value)
+    }
+
+    fn reset(&mut self) {
+        self.state = self.seed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible_test() {
+        let mut first = RngDevice::new(42);
+        let mut second = RngDevice::new(42);
+
+        let sequence: Vec<u16> = (0..8).map(|_| first.get_u16(RNG_DATA).unwrap()).collect();
+        assert!(sequence.iter().any(|&value| value != sequence[0]));
+        for value in &sequence {
+            assert_eq!(second.get_u16(RNG_DATA), Ok(*value));
+        }
+
+        first.reset();
+        assert_eq!(first.get_u16(RNG_DATA), Ok(sequence[0]));
+
+        first.set_u16(RNG_SEED, 7).unwrap();
+        second.set_u8(RNG_SEED + 1, 7).unwrap();
+        second.set_u8(RNG_SEED, 0).unwrap();
+        assert_eq!(first.get_u16(RNG_SEED), Ok(7));
+        assert_eq!(first.get_u16(RNG_DATA), second.get_u16(RNG_DATA));
+
+        second.set_u8(RNG_DATA + 1, 0xFF).unwrap();
+        assert_eq!(first.get_u16(RNG_DATA), second.get_u16(RNG_DATA));
+        assert!(first.get_u8(RNG_DATA + 1).is_ok());
+        assert_eq!(
+            first.get_u16(RNG_DATA + 1),
+            Err(VmError::MisalignedRegister {
+                address: RNG_DATA + 1
+            })
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use crate::virtual_machine::{device::Device, error::VmError};
+
+/// What a ROM device does when it receives a write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WritePolicy {
+    /// Fails the write with `VmError::ReadOnly`.
+    Reject,
+    /// Silently discards the write.
+    Ignore,
+}
+
+pub struct RomDevice {
+    memory: Box<[u8]>,
+    write_policy: WritePolicy,
+}
+
+impl RomDevice {
+    /// Creates a ROM holding the given image.
+    pub fn new(bytes: &[u8], write_policy: WritePolicy) -> RomDevice {
+        RomDevice {
+            memory: bytes.to_vec().into_boxed_slice(),
+            write_policy,
+        }
+    }
+
+    /// Returns the size of the ROM in bytes.
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Handles a write of the given number of bytes at the given address.
+    fn write(&self, address: usize, length: usize) -> Result<(), VmError> {
+        if address + length > self.memory.len() {
+            return Err(VmError::OutOfBounds { address });
+        }
+
+        match self.write_policy {
+            WritePolicy::Reject => Err(VmError::ReadOnly { address }),
+            WritePolicy::Ignore => Ok(()),
+        }
+    }
+}
+
+impl Device for RomDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        match (self.memory.get(address), self.memory.get(address + 1)) {
+            (Some(high), Some(low)) => Ok(u16::from_be_bytes([*high, *low])),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.memory
+            .get(address)
+            .copied()
+            .ok_or(VmError::OutOfBounds { address })
+    }
+
+    fn set_u16(&mut self, address: usize, _value: u16) -> Result<(), VmError> {
+        self.write(address, 2)
+    }
+
+    fn set_u8(&mut self, address: usize, _value: u8) -> Result<(), VmError> {
+        self.write(address, 1)
+    }
+
+    fn check_write(&self, address: usize) -> Result<(), VmError> {
+        self.write(address, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_policy_test() {
+        let mut rom = RomDevice::new(&[0x12, 0x34], WritePolicy::Reject);
+        assert_eq!(rom.set_u8(1, 0xFF), Err(VmError::ReadOnly { address: 1 }));
+        assert_eq!(rom.get_u16(0), Ok(0x1234));
+
+        let mut rom = RomDevice::new(&[0x12, 0x34], WritePolicy::Ignore);
+        assert_eq!(rom.set_u16(0, 0xFFFF), Ok(()));
+        assert_eq!(rom.get_u16(0), Ok(0x1234));
+        assert_eq!(rom.get_u8(2), Err(VmError::OutOfBounds { address: 2 }));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Seconds register. Reading it latches the date registers so that they stay consistent.
+pub const RTC_SECOND: usize = 0x00;
+/// Minutes register.
+pub const RTC_MINUTE: usize = 0x02;
+/// Hours register, from 0 to 23.
+pub const RTC_HOUR: usize = 0x04;
+/// Day of the month register, from 1 to 31.
+pub const RTC_DAY: usize = 0x06;
+/// Month register, from 1 to 12.
+pub const RTC_MONTH: usize = 0x08;
+/// Year register.
+pub const RTC_YEAR: usize = 0x0A;
+/// High word of the milliseconds elapsed since the reset. Reading it latches the low word.
+pub const RTC_MILLIS_HIGH: usize = 0x0C;
+/// Low word of the milliseconds elapsed since the reset.
+pub const RTC_MILLIS_LOW: usize = 0x0E;
+
+/// Number of bytes the clock occupies.
+pub const RTC_SIZE: usize = 0x10;
+
+/// Where an `RtcDevice` gets the time from.
+pub trait Clock {
+    /// Returns the milliseconds since the Unix epoch, in UTC.
+    fn unix_millis(&self) -> u64;
+
+    /// Returns the milliseconds elapsed since an arbitrary point. It never goes backwards.
+    fn monotonic_millis(&self) -> u64;
+}
+
+/// The clock of the host.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64)
+    }
+
+    fn monotonic_millis(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// A clock that only moves when told to, e.g. by tests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FixedClock {
+    unix_millis: u64,
+    monotonic_millis: u64,
+}
+
+impl FixedClock {
+    pub fn new(unix_millis: u64) -> FixedClock {
+        FixedClock {
+            unix_millis,
+            monotonic_millis: 0,
+        }
+    }
+
+    /// Moves both clocks forward by the given number of milliseconds.
+    pub fn advance(&mut self, millis: u64) {
+        self.unix_millis += millis;
+        self.monotonic_millis += millis;
+    }
+}
+
+impl Clock for FixedClock {
+    fn unix_millis(&self) -> u64 {
+        self.unix_millis
+    }
+
+    fn monotonic_millis(&self) -> u64 {
+        self.monotonic_millis
+    }
+}
+
+/// A real-time clock giving the UTC date and a millisecond counter. Its registers are read-only.
+pub struct RtcDevice<C: Clock = SystemClock> {
+    clock: C,
+    /// Monotonic time of the last reset.
+    start_millis: u64,
+    latched: [u16; RTC_SIZE / 2],
+}
+
+impl<C: Clock> RtcDevice<C> {
+    pub fn new(clock: C) -> RtcDevice<C> {
+        let start_millis = clock.monotonic_millis();
+
+        RtcDevice {
+            clock,
+            start_millis,
+            latched: [0; RTC_SIZE / 2],
+        }
+    }
+
+    /// Returns the clock for modification, e.g. to advance a `FixedClock`.
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    fn latch_date(&mut self) {
+        let seconds = self.clock.unix_millis() / 1000;
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+
+        self.latched[RTC_SECOND / 2] = (seconds % 60) as u16;
+        self.latched[RTC_MINUTE / 2] = (seconds / 60 % 60) as u16;
+        self.latched[RTC_HOUR / 2] = (seconds / 3600 % 24) as u16;
+        self.latched[RTC_DAY / 2] = day;
+        self.latched[RTC_MONTH / 2] = month;
+        self.latched[RTC_YEAR / 2] = year as u16;
+    }
+
+    fn latch_millis(&mut self) {
+        let millis = self.clock.monotonic_millis() - self.start_millis;
+
+        self.latched[RTC_MILLIS_HIGH / 2] = (millis >> 16) as u16;
+        self.latched[RTC_MILLIS_LOW / 2] = millis as u16;
+    }
+}
+
+impl<C: Clock> WordRegisters for RtcDevice<C> {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            RTC_SECOND => self.latch_date(),
+            RTC_MILLIS_HIGH => self.latch_millis(),
+            _ if address >= RTC_SIZE => return Err(VmError::OutOfBounds { address }),
+            _ => {}
+        }
+
+        Ok(self.latched[address / 2])
+    }
+
+    fn set_register(&mut self, address: usize, _value: u16) -> Result<(), VmError> {
+        self.check_write(address)
+    }
+}
+
+impl<C: Clock> Device for RtcDevice<C> {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        // Only the high byte latches, so that the low byte matches it
+        let [high, low] = if address & 1 == 0 {
+            self.get_register(address)?
+        } else if address < RTC_SIZE {
+            self.latched[address / 2]
+        } else {
+            return Err(VmError::OutOfBounds { address });
+        }
+        .to_be_bytes();
+
+        Ok(if address & 1 == 0 { high } else { low })
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        // Writes fail anyway, and reading the register first would latch it
+        self.set_register(address, u16::from(value))
+    }
+
+    fn check_write(&self, address: usize) -> Result<(), VmError> {
+        if address < RTC_SIZE {
+            Err(VmError::ReadOnly { address })
+        } else {
+            Err(VmError::OutOfBounds { address })
+        }
+    }
+
+    fn reset(&mut self) {
+        self.start_millis = self.clock.monotonic_millis();
+        self.latched = [0; RTC_SIZE / 2];
+    }
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date of the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u16, u16) {
+    // Counts from 0000-03-01 so that leap days end the 400-year eras
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u16;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u16;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{memory::Memory, memory_mapper::MemoryMapper};
+
+    #[test]
+    fn date_test() {
+        // 2024-02-29 23:59:58.500 UTC
+        let mut rtc = RtcDevice::new(FixedClock::new(1_709_251_198_500));
+
+        assert_eq!(rtc.get_u16(RTC_SECOND), Ok(58));
+        rtc.clock_mut().advance(2000);
+        assert_eq!(rtc.get_u16(RTC_MINUTE), Ok(59));
+        assert_eq!(rtc.get_u16(RTC_DAY), Ok(29));
+
+        assert_eq!(rtc.get_u8(RTC_SECOND + 1), Ok(58));
+        assert_eq!(rtc.get_u8(RTC_SECOND), Ok(0));
+        assert_eq!(rtc.get_u8(RTC_SECOND + 1), Ok(0));
+        assert_eq!(rtc.get_u16(RTC_MINUTE), Ok(0));
+        assert_eq!(rtc.get_u16(RTC_HOUR), Ok(0));
+        assert_eq!(rtc.get_u16(RTC_DAY), Ok(1));
+        assert_eq!(rtc.get_u16(RTC_MONTH), Ok(3));
+        assert_eq!(rtc.get_u16(RTC_YEAR), Ok(2024));
+
+        assert_eq!(
+            rtc.set_u16(RTC_YEAR, 1999),
+            Err(VmError::ReadOnly { address: RTC_YEAR })
+        );
+    }
+
+    #[test]
+    fn millis_test() {
+        let mut rtc = RtcDevice::new(FixedClock::new(0));
+        rtc.clock_mut().advance(70_000);
+
+        assert_eq!(rtc.get_u16(RTC_MILLIS_HIGH), Ok(1));
+        rtc.clock_mut().advance(1);
+        assert_eq!(rtc.get_u16(RTC_MILLIS_LOW), Ok(4464));
+
+        rtc.reset();
+        rtc.clock_mut().advance(5);
+        assert_eq!(rtc.get_u16(RTC_MILLIS_HIGH), Ok(0));
+        assert_eq!(rtc.get_u16(RTC_MILLIS_LOW), Ok(5));
+    }
+
+    #[test]
+    fn load_image_test() {
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map(Box::new(Memory::new(0x10)), 0x00, 0x0F, true)
+            .unwrap();
+        memory_mapper
+            .map(
+                Box::new(RtcDevice::new(FixedClock::new(0))),
+                0x10,
+                0x1F,
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(
+            memory_mapper.load_image(&[0xFF; 4], 0x0E, "program"),
+            Err(VmError::ReadOnly { address: 0x00 })
+        );
+        assert_eq!(memory_mapper.get_u16(0x0E), Ok(0x0000));
+    }
+
+    #[test]
+    fn civil_from_days_test() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20_000), (2024, 10, 4));
+    }
+}
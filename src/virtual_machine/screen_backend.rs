@@ -0,0 +1,234 @@
+use std::io::{self, Write};
+
+/// Display attributes of a character.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Attributes {
+    pub bold: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    /// Index in the 256-colour ANSI palette, or None for the default colour.
+    pub foreground: Option<u8>,
+    /// Index in the 256-colour ANSI palette, or None for the default colour.
+    pub background: Option<u8>,
+}
+
+/// A character drawn on the screen with its attributes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub character: char,
+    pub attributes: Attributes,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            character: ' ',
+            attributes: Attributes::default(),
+        }
+    }
+}
+
+/// Where a `ScreenDevice` sends its output.
+pub trait ScreenBackend {
+    /// Clears every cell of the screen.
+    fn erase(&mut self);
+
+    /// Draws the given cell at the given column and row, both starting at 0.
+    fn draw(&mut self, column: usize, row: usize, cell: Cell);
+
+    /// Moves the rows of a screen of the given height up by the given number of lines,
+    /// or down if it is negative. The rows that appear are blank.
+    fn scroll(&mut self, lines: isize, height: usize);
+
+    /// Shows or hides the cursor.
+    fn set_cursor_visible(&mut self, visible: bool);
+
+    /// Returns the number of columns and rows of the backend, or None if it can draw a screen of any size.
+    fn size(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Changes the number of columns and rows of the backend.
+    fn resize(&mut self, _width: usize, _height: usize) {}
+}
+
+/// Draws the screen on the terminal with ANSI escape sequences.
+#[derive(Debug, Default)]
+pub struct AnsiBackend;
+
+impl ScreenBackend for AnsiBackend {
+    fn erase(&mut self) {
+        print!("\x1b[2J");
+    }
+
+    fn draw(&mut self, column: usize, row: usize, cell: Cell) {
+        // Each column is two characters wide so that the screen looks square
+        print!("\x1b[{};{}H", row + 1, (column + 1) * 2);
+        print!("{}", select_graphic_rendition(cell.attributes));
+        print!("{}", cell.character);
+        io::stdout().flush().unwrap();
+    }
+
+    fn scroll(&mut self, lines: isize, height: usize) {
+        // Restrict scrolling to the rows of the screen, then restore the whole terminal
+        print!("\x1b[1;{}r", height);
+        if lines > 0 {
+            print!("\x1b[{}S", lines);
+        } else if lines < 0 {
+            print!("\x1b[{}T", -lines);
+        }
+        print!("\x1b[r");
+        io::stdout().flush().unwrap();
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        if visible {
+            print!("\x1b[?25h");
+        } else {
+            print!("\x1b[?25l");
+        }
+        io::stdout().flush().unwrap();
+    }
+}
+
+/// Returns the escape sequence that selects the given attributes.
+fn select_graphic_rendition(attributes: Attributes) -> String {
+    let mut parameters = vec![String::from("0")];
+    if attributes.bold {
+        parameters.push(String::from("1"));
+    }
+    if attributes.underline {
+        parameters.push(String::from("4"));
+    }
+    if attributes.inverse {
+        parameters.push(String::from("7"));
+    }
+    if let Some(colour) = attributes.foreground {
+        parameters.push(colour_parameter(colour, 30, 90, 38));
+    }
+    if let Some(colour) = attributes.background {
+        parameters.push(colour_parameter(colour, 40, 100, 48));
+    }
+
+    format!("\x1b[{}m", parameters.join(";"))
+}
+
+/// Returns the parameter selecting the given colour, using the 16-colour codes when possible.
+fn colour_parameter(colour: u8, normal: u8, bright: u8, extended: u8) -> String {
+    match colour {
+        0..=7 => format!("{}", normal + colour),
+        8..=15 => format!("{}", bright + colour - 8),
+        _ => format!("{};5;{}", extended, colour),
+    }
+}
+
+/// Keeps the screen in memory so that it can be inspected, e.g. by tests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BufferBackend {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    cursor_visible: bool,
+}
+
+impl BufferBackend {
+    pub fn new(width: usize, height: usize) -> BufferBackend {
+        BufferBackend {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            cursor_visible: true,
+        }
+    }
+
+    pub fn is_cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the cell at the given column and row, if it is on the screen.
+    pub fn cell(&self, column: usize, row: usize) -> Option<Cell> {
+        if column < self.width && row < self.height {
+            Some(self.cells[row * self.width + column])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the characters of the given row.
+    pub fn row_text(&self, row: usize) -> String {
+        self.cells[row * self.width..(row + 1) * self.width]
+            .iter()
+            .map(|cell| cell.character)
+            .collect()
+    }
+
+    /// Returns the characters of the whole screen, one line per row.
+    pub fn text(&self) -> String {
+        (0..self.height)
+            .map(|row| self.row_text(row))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl ScreenBackend for BufferBackend {
+    fn erase(&mut self) {
+        for cell in self.cells.iter_mut() {
+            *cell = Cell::default();
+        }
+    }
+
+    fn draw(&mut self, column: usize, row: usize, cell: Cell) {
+        if column < self.width && row < self.height {
+            self.cells[row * self.width + column] = cell;
+        }
+    }
+
+    fn scroll(&mut self, lines: isize, height: usize) {
+        let height = height.min(self.height);
+        let distance = lines.unsigned_abs().min(height);
+        let rows = &mut self.cells[..height * self.width];
+
+        if lines > 0 {
+            rows.rotate_left(distance * self.width);
+            for cell in rows[(height - distance) * self.width..].iter_mut() {
+                *cell = Cell::default();
+            }
+        } else {
+            rows.rotate_right(distance * self.width);
+            for cell in rows[..distance * self.width].iter_mut() {
+                *cell = Cell::default();
+            }
+        }
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+    }
+
+    fn size(&self) -> Option<(usize, usize)> {
+        Some((self.width, self.height))
+    }
+
+    /// Keeps the cells that are still on the screen, the new ones are blank.
+    fn resize(&mut self, width: usize, height: usize) {
+        let mut cells = vec![Cell::default(); width * height];
+        for row in 0..height.min(self.height) {
+            for column in 0..width.min(self.width) {
+                cells[row * width + column] = self.cells[row * self.width + column];
+            }
+        }
+
+        self.width = width;
+        self.height = height;
+        self.cells = cells;
+    }
+}
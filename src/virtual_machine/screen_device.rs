@@ -1,71 +1,311 @@
-use crate::virtual_machine::device::Device;
-use std::{
-    convert::TryInto,
-    io::{self, Write},
+use crate::virtual_machine::{
+    device::Device,
+    error::VmError,
+    screen_backend::{AnsiBackend, Attributes, Cell, ScreenBackend},
 };
 
-pub struct ScreenDevice;
+const DEFAULT_WIDTH: usize = 16;
+const DEFAULT_HEIGHT: usize = 16;
 
-impl ScreenDevice {
-    fn erase_screen(&self) {
-        print!("\x1b[2J");
+pub const COMMAND_NONE: u8 = 0x00;
+pub const COMMAND_BOLD: u8 = 0x01;
+pub const COMMAND_REGULAR: u8 = 0x02;
+pub const COMMAND_UNDERLINE: u8 = 0x03;
+pub const COMMAND_INVERSE: u8 = 0x04;
+pub const COMMAND_FOREGROUND: u8 = 0x10;
+pub const COMMAND_BACKGROUND: u8 = 0x11;
+pub const COMMAND_DEFAULT_COLOURS: u8 = 0x12;
+pub const COMMAND_SHOW_CURSOR: u8 = 0x20;
+pub const COMMAND_HIDE_CURSOR: u8 = 0x21;
+pub const COMMAND_SCROLL_UP: u8 = 0x30;
+pub const COMMAND_SCROLL_DOWN: u8 = 0x31;
+pub const COMMAND_ERASE: u8 = 0xFF;
+
+/// A character screen. Each address maps to a cell, row by row.
+///
+/// Writing a u8 draws the character at the cell of the address. Writing a u16
+/// sends the high byte as a command and the low byte as its argument:
+///
+/// | Command | Effect                                                        | Draws |
+/// |---------|---------------------------------------------------------------|-------|
+/// | `0x00`  | Draws the character                                           | Yes   |
+/// | `0x01`  | Turns bold on, then draws the character                       | Yes   |
+/// | `0x02`  | Clears all attributes and colours, then draws the character   | Yes   |
+/// | `0x03`  | Turns underline on, then draws the character                  | Yes   |
+/// | `0x04`  | Turns inverse on, then draws the character                    | Yes   |
+/// | `0x10`  | Sets the foreground to the ANSI colour in the low byte        | No    |
+/// | `0x11`  | Sets the background to the ANSI colour in the low byte        | No    |
+/// | `0x12`  | Restores the default colours                                  | No    |
+/// | `0x20`  | Shows the cursor                                              | No    |
+/// | `0x21`  | Hides the cursor                                              | No    |
+/// | `0x30`  | Scrolls up by the number of lines in the low byte             | No    |
+/// | `0x31`  | Scrolls down by the number of lines in the low byte           | No    |
+/// | `0xFF`  | Erases the screen, then draws the character                   | Yes   |
+///
+/// Colours are indices in the 256-colour ANSI palette, the first 16 being the
+/// standard colours. Other commands draw the character without any effect.
+/// Attributes and colours apply to every character drawn afterwards.
+///
+/// Every command used to draw the low byte. Programs that used `0x10` to `0x12`,
+/// `0x20`, `0x21`, `0x30` or `0x31` as commands without effect no longer draw
+/// with them, and `0x03` and `0x04` now change the attributes.
+///
+/// Only the cells of the screen can be drawn: writes at or past
+/// `width * height` fail with `VmError::OutOfBounds` instead of drawing
+/// below the last row.
+pub struct ScreenDevice<B: ScreenBackend = AnsiBackend> {
+    backend: B,
+    width: usize,
+    height: usize,
+    attributes: Attributes,
+}
+
+impl ScreenDevice<AnsiBackend> {
+    /// Creates a screen that draws on the terminal.
+    pub fn new() -> ScreenDevice<AnsiBackend> {
+        ScreenDevice::with_backend(AnsiBackend)
     }
+}
 
-    fn move_to(&self, x: usize, y: usize) {
-        print!("\x1b[{};{}H", y, x);
+impl Default for ScreenDevice<AnsiBackend> {
+    fn default() -> ScreenDevice<AnsiBackend> {
+        ScreenDevice::new()
     }
+}
+
+impl<B: ScreenBackend> ScreenDevice<B> {
+    /// Creates a screen that sends its output to the given backend. The screen has the size
+    /// of the backend, or 16×16 if the backend can draw a screen of any size.
+    pub fn with_backend(backend: B) -> ScreenDevice<B> {
+        let (width, height) = backend.size().unwrap_or((DEFAULT_WIDTH, DEFAULT_HEIGHT));
 
-    fn set_bold(&self) {
-        print!("\x1b[1m");
+        ScreenDevice {
+            backend,
+            width,
+            height,
+            attributes: Attributes::default(),
+        }
     }
 
-    fn set_regular(&self) {
-        print!("\x1b[0m");
+    /// Sets the number of columns and rows of the screen and resizes the backend to match.
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.backend.resize(width, height);
     }
-}
 
-impl Device for ScreenDevice {
-    fn get_u16(&self, _address: usize) -> Result<u16, String> {
-        Ok(0)
+    pub fn width(&self) -> usize {
+        self.width
     }
 
-    fn get_u8(&self, _address: usize) -> Result<u8, String> {
-        Ok(0)
+    pub fn height(&self) -> usize {
+        self.height
     }
 
-    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), String> {
-        let command: u8 = ((value & 0xFF00) >> 8)
-            .try_into()
-            .map_err(|_| "set_u16: Failed to convert u16 to u8")?;
+    /// Returns the backend of the screen.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
 
+    /// Applies the given command. Returns true if the argument must be drawn as a character.
+    fn apply_command(&mut self, command: u8, argument: u8) -> bool {
         match command {
-            0xFF => self.erase_screen(),
-            0x01 => self.set_bold(),
-            0x02 => self.set_regular(),
+            COMMAND_ERASE => self.backend.erase(),
+            COMMAND_BOLD => self.attributes.bold = true,
+            COMMAND_REGULAR => self.attributes = Attributes::default(),
+            COMMAND_UNDERLINE => self.attributes.underline = true,
+            COMMAND_INVERSE => self.attributes.inverse = true,
+            COMMAND_FOREGROUND => {
+                self.attributes.foreground = Some(argument);
+                return false;
+            }
+            COMMAND_BACKGROUND => {
+                self.attributes.background = Some(argument);
+                return false;
+            }
+            COMMAND_DEFAULT_COLOURS => {
+                self.attributes.foreground = None;
+                self.attributes.background = None;
+                return false;
+            }
+            COMMAND_SHOW_CURSOR => {
+                self.backend.set_cursor_visible(true);
+                return false;
+            }
+            COMMAND_HIDE_CURSOR => {
+                self.backend.set_cursor_visible(false);
+                return false;
+            }
+            COMMAND_SCROLL_UP => {
+                self.backend.scroll(argument as isize, self.height);
+                return false;
+            }
+            COMMAND_SCROLL_DOWN => {
+                self.backend.scroll(-(argument as isize), self.height);
+                return false;
+            }
             _ => {
-                // Do nothing
+                // Draw the character only
             }
         }
-        if command == 0xFF {
-            self.erase_screen();
+
+        true
+    }
+}
+
+impl<B: ScreenBackend> Device for ScreenDevice<B> {
+    fn get_u16(&mut self, _address: usize) -> Result<u16, VmError> {
+        Ok(0)
+    }
+
+    fn get_u8(&mut self, _address: usize) -> Result<u8, VmError> {
+        Ok(0)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        let [command, argument] = value.to_be_bytes();
+
+        if self.apply_command(command, argument) {
+            self.set_u8(address, argument)?;
         }
 
-        let character: u8 = (value & 0x00FF)
-            .try_into()
-            .map_err(|_| "set_u16: Failed to convert u16 to u8")?;
-        self.set_u8(address, character)
+        Ok(())
     }
 
-    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), String> {
-        let x = (address % 16) + 1;
-        let y = (address / 16) + 1;
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        if address >= self.width * self.height {
+            return Err(VmError::OutOfBounds { address });
+        }
+
+        let column = address % self.width;
+        let row = address / self.width;
 
-        self.move_to(x * 2, y);
-        let character = String::from_utf8(vec![value])
-            .map_err(|err| format!("set_u16: Failed to get UTF-8 character from u8: {}", err))?;
-        print!("{}", character);
-        io::stdout().flush().unwrap();
+        let character = String::from_utf8(vec![value]).map_err(|err| VmError::Device {
+            address,
+            message: format!("Failed to get UTF-8 character from u8: {}", err),
+        })?;
+        let cell = Cell {
+            character: character.chars().next().unwrap_or_default(),
+            attributes: self.attributes,
+        };
+        self.backend.draw(column, row, cell);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        memory::Memory, memory_mapper::MemoryMapper, screen_backend::BufferBackend,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn draw_test() {
+        let mut screen = ScreenDevice::with_backend(BufferBackend::new(16, 16));
+
+        screen.set_u8(0x00, b'H').unwrap();
+        screen.set_u16(0x01, 0x0169).unwrap();
+        screen.set_u16(0x12, 0x0221).unwrap();
+        screen.set_u16(0x02, 0x0521).unwrap();
+
+        let backend = screen.backend();
+        assert_eq!(backend.row_text(0), "Hi!             ");
+        assert_eq!(backend.row_text(1), "  !             ");
+        assert!(!backend.cell(0, 0).unwrap().attributes.bold);
+        assert!(backend.cell(1, 0).unwrap().attributes.bold);
+        assert!(!backend.cell(2, 1).unwrap().attributes.bold);
+    }
+
+    #[test]
+    fn erase_test() {
+        let mut screen = ScreenDevice::with_backend(BufferBackend::new(16, 16));
+
+        screen.set_u8(0x00, b'a').unwrap();
+        screen.set_u16(0x21, 0xFF62).unwrap();
+
+        assert_eq!(screen.backend().cell(0, 0).unwrap().character, ' ');
+        assert_eq!(screen.backend().cell(1, 2).unwrap().character, 'b');
+        assert!(screen.set_u8(0x00, 0x80).is_err());
+    }
+
+    #[test]
+    fn mapped_screen_test() {
+        let screen = Rc::new(RefCell::new(ScreenDevice::with_backend(
+            BufferBackend::new(16, 16),
+        )));
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(screen.clone()), 0x3000, 0x30FF, true)
+            .unwrap();
+
+        memory_mapper.set_u16(0x30FE, 0x014F).unwrap();
+        memory_mapper.set_u8(0x30FF, b'K').unwrap();
+
+        assert_eq!(screen.borrow().backend().row_text(15), "              OK");
+        assert_eq!(memory_mapper.device_name(0x3000), Some("ScreenDevice"));
+    }
+
+    #[test]
+    fn attributes_test() {
+        let mut screen = ScreenDevice::with_backend(BufferBackend::new(16, 16));
+
+        screen.set_u16(0x00, 0x1009).unwrap();
+        screen.set_u16(0x00, 0x11EA).unwrap();
+        screen.set_u16(0x00, 0x0341).unwrap();
+        screen.set_u16(0x01, 0x0442).unwrap();
+        screen.set_u16(0x02, 0x0243).unwrap();
+
+        let backend = screen.backend();
+        assert_eq!(backend.row_text(0), "ABC             ");
+        assert_eq!(
+            backend.cell(0, 0).unwrap().attributes,
+            Attributes {
+                bold: false,
+                underline: true,
+                inverse: false,
+                foreground: Some(0x09),
+                background: Some(0xEA),
+            }
+        );
+        assert!(backend.cell(1, 0).unwrap().attributes.inverse);
+        assert_eq!(
+            backend.cell(2, 0).unwrap().attributes,
+            Attributes::default()
+        );
+    }
+
+    #[test]
+    fn size_and_scroll_test() {
+        let mut screen = ScreenDevice::with_backend(BufferBackend::new(2, 2));
+        assert_eq!((screen.width(), screen.height()), (2, 2));
+
+        screen.set_u8(0x00, b'z').unwrap();
+        screen.set_size(4, 3);
+        assert_eq!(screen.backend().text(), "z   \n    \n    ");
+
+        for (address, character) in b"abcdefghijkl".iter().enumerate() {
+            screen.set_u8(address, *character).unwrap();
+        }
+        assert_eq!(
+            screen.set_u8(12, b'm'),
+            Err(VmError::OutOfBounds { address: 12 })
+        );
+
+        screen.set_u16(0x00, 0x3001).unwrap();
+        assert_eq!(screen.backend().text(), "efgh\nijkl\n    ");
+
+        screen.set_u16(0x00, 0x3102).unwrap();
+        assert_eq!(screen.backend().text(), "    \n    \nefgh");
+
+        screen.set_u16(0x00, 0x2100).unwrap();
+        assert!(!screen.backend().is_cursor_visible());
+        screen.set_u16(0x00, 0x2000).unwrap();
+        assert!(screen.backend().is_cursor_visible());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+};
+
+/// Reload register: the value the counter starts from. Writing it also loads the counter.
+pub const TIMER_RELOAD: usize = 0x00;
+/// Count register: the cycles left before the timer expires.
+pub const TIMER_COUNT: usize = 0x02;
+/// Control register, made of the `CONTROL_*` flags.
+pub const TIMER_CONTROL: usize = 0x04;
+/// Status register: bit 0 is set when the timer expires. Writing clears it.
+pub const TIMER_STATUS: usize = 0x06;
+
+/// Control bit that starts the timer.
+pub const CONTROL_ENABLE: u16 = 0x01;
+/// Control bit that reloads the counter on expiry instead of stopping the timer.
+pub const CONTROL_PERIODIC: u16 = 0x02;
+/// Control bit that enables the expiry interrupt.
+pub const CONTROL_INTERRUPT_ENABLE: u16 = 0x04;
+
+/// Status bit set when the timer expires.
+pub const STATUS_EXPIRED: u16 = 0x01;
+
+/// A timer counting down the CPU cycles.
+///
+/// The timer only advances when the memory mapper is ticked, so the same
+/// program always sees the same timing. In one-shot mode it stops when it
+/// expires, in periodic mode it starts again from the reload value.
+#[derive(Clone, Debug, Default)]
+pub struct TimerDevice {
+    reload: u16,
+    count: u16,
+    control: u16,
+    status: u16,
+    interrupt_vector: Option<u16>,
+}
+
+impl TimerDevice {
+    pub fn new() -> TimerDevice {
+        TimerDevice::default()
+    }
+
+    /// Sets the interrupt vector requested when the timer expires.
+    pub fn set_interrupt_vector(&mut self, interrupt_vector: Option<u16>) {
+        self.interrupt_vector = interrupt_vector;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.control & CONTROL_ENABLE != 0
+    }
+
+    pub fn has_expired(&self) -> bool {
+        self.status & STATUS_EXPIRED != 0
+    }
+}
+
+impl WordRegisters for TimerDevice {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            TIMER_RELOAD => Ok(self.reload),
+            TIMER_COUNT => Ok(self.count),
+            TIMER_CONTROL => Ok(self.control),
+            TIMER_STATUS => Ok(self.status),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        match address {
+            TIMER_RELOAD => {
+                self.reload = value;
+                self.count = value;
+            }
+            TIMER_COUNT => self.count = value,
+            TIMER_CONTROL => self.control = value,
+            TIMER_STATUS => self.status = 0,
+            _ => return Err(VmError::OutOfBounds { address }),
+        }
+
+        Ok(())
+    }
+}
+
+impl Device for TimerDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.set_register_u8(address, value)
+    }
+
+    fn reset(&mut self) {
+        *self = TimerDevice {
+            interrupt_vector: self.interrupt_vector,
+            ..TimerDevice::default()
+        };
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if !self.is_enabled() || self.count == 0 {
+            return;
+        }
+
+        if cycles < u64::from(self.count) {
+            self.count -= cycles as u16;
+            return;
+        }
+
+        self.status |= STATUS_EXPIRED;
+
+        if self.control & CONTROL_PERIODIC != 0 && self.reload != 0 {
+            // The cycles past the expiry count towards the next period
+            let overshoot = (cycles - u64::from(self.count)) % u64::from(self.reload);
+            self.count = self.reload - overshoot as u16;
+        } else {
+            self.count = 0;
+            self.control &= !CONTROL_ENABLE;
+        }
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        if self.has_expired() && self.control & CONTROL_INTERRUPT_ENABLE != 0 {
+            self.interrupt_vector
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        cpu::CPU, instructions, memory::Memory, memory_mapper::MemoryMapper,
+    };
+
+    #[test]
+    fn one_shot_test() {
+        let mut timer = TimerDevice::new();
+        timer.set_u16(TIMER_RELOAD, 10).unwrap();
+        timer.set_u16(TIMER_CONTROL, CONTROL_ENABLE).unwrap();
+
+        timer.tick(4);
+        assert_eq!(timer.get_u16(TIMER_COUNT), Ok(6));
+        assert!(!timer.has_expired());
+
+        timer.tick(7);
+        assert_eq!(timer.get_u16(TIMER_COUNT), Ok(0));
+        assert_eq!(timer.get_u16(TIMER_STATUS), Ok(STATUS_EXPIRED));
+        assert!(!timer.is_enabled());
+
+        timer.set_u8(TIMER_STATUS + 1, 0xFF).unwrap();
+        assert!(!timer.has_expired());
+    }
+
+    #[test]
+    fn periodic_test() {
+        let mut timer = TimerDevice::new();
+        timer.set_u16(TIMER_RELOAD, 10).unwrap();
+        timer
+            .set_u16(TIMER_CONTROL, CONTROL_ENABLE | CONTROL_PERIODIC)
+            .unwrap();
+
+        timer.tick(23);
+        assert_eq!(timer.get_u16(TIMER_COUNT), Ok(7));
+        assert!(timer.has_expired());
+        assert!(timer.is_enabled());
+        assert_eq!(timer.get_u8(TIMER_COUNT + 1), Ok(7));
+        assert_eq!(
+            timer.get_u16(TIMER_COUNT + 1),
+            Err(VmError::MisalignedRegister {
+                address: TIMER_COUNT + 1
+            })
+        );
+        assert_eq!(timer.irq_pending(), None);
+    }
+
+    #[test]
+    fn timer_interrupt_test() {
+        let mut timer = TimerDevice::new();
+        timer.set_interrupt_vector(Some(2));
+
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(timer), 0x4000, 0x4007, true)
+            .unwrap();
+
+        #[rustfmt::skip]
+        let program = [
+            instructions::MOV_LIT_MEM, 0x00, 0x09, 0x40, 0x00, // mov $0009, &4000
+            instructions::MOV_LIT_MEM, 0x00, 0x05, 0x40, 0x04, // mov $0005, &4004
+            instructions::INC_REG, 1,                          // inc acc
+            instructions::JMP_NOT_EQ, 0xFF, 0xFF, 0x00, 0x0A,  // jne $ffff, &000a
+        ];
+        #[rustfmt::skip]
+        let handler = [
+            instructions::HLT,
+        ];
+        memory_mapper
+            .load_image(&program, 0x0000, "program")
+            .unwrap();
+        memory_mapper
+            .load_image(&handler, 0x2000, "handler")
+            .unwrap();
+        memory_mapper.set_u16(0x1004, 0x2000).unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        cpu.run().unwrap();
+
+        // The instruction enabling the timer takes the first four cycles, inc one and jne three
+        assert_eq!(cpu.get_register("acc"), Ok(2));
+    }
+}
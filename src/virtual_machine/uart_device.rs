@@ -0,0 +1,406 @@
+use crate::virtual_machine::{
+    device::{Device, WordRegisters},
+    error::VmError,
+    keyboard_device::{InputSource, StdinInput},
+};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+#[cfg(unix)]
+use crate::virtual_machine::keyboard_device::ReaderInput;
+#[cfg(unix)]
+use std::{
+    fs::OpenOptions,
+    io::Read,
+    net::Shutdown,
+    os::unix::{fs::OpenOptionsExt, net::UnixStream},
+};
+
+/// Data register: writing queues a byte to transmit, reading pops a received byte or returns 0.
+pub const UART_DATA: usize = 0x00;
+/// Status register, made of the `STATUS_*` flags.
+pub const UART_STATUS: usize = 0x02;
+/// Control register, made of the `CONTROL_*` flags.
+pub const UART_CONTROL: usize = 0x04;
+
+/// Status bit set while a received byte is available.
+pub const STATUS_RX_AVAILABLE: u8 = 0x01;
+/// Status bit set while the transmit FIFO has room for another byte.
+pub const STATUS_TX_READY: u8 = 0x02;
+/// Status bit set when a byte was written while the transmit FIFO was full.
+pub const STATUS_TX_OVERRUN: u8 = 0x04;
+
+/// Control bit that enables the receive interrupt.
+pub const CONTROL_RX_INTERRUPT_ENABLE: u8 = 0x01;
+
+/// Number of bytes each FIFO holds.
+pub const FIFO_SIZE: usize = 16;
+
+/// Input read from a host stream opened in non-blocking mode, without a background thread.
+#[cfg(unix)]
+struct NonBlockingInput<R: Read> {
+    reader: R,
+}
+
+#[cfg(unix)]
+impl<R: Read> InputSource for NonBlockingInput<R> {
+    fn poll(&mut self) -> Option<u8> {
+        let mut byte = [0];
+
+        match self.reader.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+}
+
+/// A serial port bridged to host byte streams.
+///
+/// Every register is 16 bits wide with its value in the low byte, so that the
+/// `mov` instructions move a single byte. The high bytes read as 0 and ignore
+/// writes.
+///
+/// Transmitted bytes are written to the output as they are, without any
+/// escape sequence, when the device is ticked. Received bytes are moved from
+/// the input to the receive FIFO as long as there is room for them.
+///
+/// Dropping the device flushes the transmit FIFO and closes the host stream,
+/// so that the other end sees the end of the stream.
+pub struct UartDevice {
+    input: Option<Box<dyn InputSource>>,
+    output: Box<dyn Write>,
+    /// The socket the device is bridged to, shut down when the device is dropped.
+    #[cfg(unix)]
+    socket: Option<UnixStream>,
+    rx_fifo: VecDeque<u8>,
+    tx_fifo: VecDeque<u8>,
+    status: u8,
+    control: u8,
+    interrupt_vector: Option<u16>,
+}
+
+impl UartDevice {
+    pub fn new(input: Option<Box<dyn InputSource>>, output: Box<dyn Write>) -> UartDevice {
+        UartDevice {
+            input,
+            output,
+            #[cfg(unix)]
+            socket: None,
+            rx_fifo: VecDeque::with_capacity(FIFO_SIZE),
+            tx_fifo: VecDeque::with_capacity(FIFO_SIZE),
+            status: 0,
+            control: 0,
+            interrupt_vector: None,
+        }
+    }
+
+    /// Bridges the serial port to the standard input and output of the host.
+    /// The standard input is shared with every other `StdinInput`, e.g. of a keyboard,
+    /// and each of them receives every byte.
+    pub fn stdio() -> UartDevice {
+        UartDevice::new(Some(Box::new(StdinInput::new())), Box::new(io::stdout()))
+    }
+
+    /// Logs the transmitted bytes to a file. Nothing is ever received.
+    pub fn log_file<P: AsRef<Path>>(path: P) -> Result<UartDevice, io::Error> {
+        Ok(UartDevice::new(None, Box::new(File::create(path)?)))
+    }
+
+    /// Bridges the serial port to an existing terminal or pseudo-terminal, e.g. `/dev/pts/3`.
+    #[cfg(unix)]
+    pub fn terminal<P: AsRef<Path>>(path: P) -> Result<UartDevice, io::Error> {
+        // A terminal cannot be shut down, so it is read without blocking rather than by
+        // a thread that would keep it open after the device is dropped
+        let input = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)?;
+        let output = OpenOptions::new().write(true).open(path)?;
+
+        Ok(UartDevice::new(
+            Some(Box::new(NonBlockingInput { reader: input })),
+            Box::new(output),
+        ))
+    }
+
+    /// Bridges the serial port to the Unix domain socket listening at the given path.
+    #[cfg(unix)]
+    pub fn unix_socket<P: AsRef<Path>>(path: P) -> Result<UartDevice, io::Error> {
+        UartDevice::from_unix_stream(UnixStream::connect(path)?)
+    }
+
+    /// Bridges the serial port to a connected Unix domain socket.
+    #[cfg(unix)]
+    pub fn from_unix_stream(stream: UnixStream) -> Result<UartDevice, io::Error> {
+        let input = ReaderInput::new(stream.try_clone()?);
+
+        let mut uart = UartDevice::new(Some(Box::new(input)), Box::new(stream.try_clone()?));
+        uart.socket = Some(stream);
+        Ok(uart)
+    }
+
+    /// Sets the interrupt vector requested when a byte is received.
+    pub fn set_interrupt_vector(&mut self, interrupt_vector: Option<u16>) {
+        self.interrupt_vector = interrupt_vector;
+    }
+
+    /// Writes the transmit FIFO to the output.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        let (first, second) = self.tx_fifo.as_slices();
+        self.output.write_all(first)?;
+        self.output.write_all(second)?;
+        self.tx_fifo.clear();
+
+        self.output.flush()
+    }
+
+    fn poll_input(&mut self) {
+        if let Some(input) = &mut self.input {
+            while self.rx_fifo.len() < FIFO_SIZE {
+                match input.poll() {
+                    Some(byte) => self.rx_fifo.push_back(byte),
+                    None => break,
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = self.status;
+
+        if !self.rx_fifo.is_empty() {
+            status |= STATUS_RX_AVAILABLE;
+        }
+        if self.tx_fifo.len() < FIFO_SIZE {
+            status |= STATUS_TX_READY;
+        }
+
+        status
+    }
+}
+
+impl WordRegisters for UartDevice {
+    fn get_register(&mut self, address: usize) -> Result<u16, VmError> {
+        match address {
+            UART_DATA => {
+                let byte = self.rx_fifo.pop_front().unwrap_or(0);
+                self.poll_input();
+                Ok(byte.into())
+            }
+            UART_STATUS => Ok(self.status().into()),
+            UART_CONTROL => Ok(self.control.into()),
+            _ => Err(VmError::OutOfBounds { address }),
+        }
+    }
+
+    fn set_register(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        let [_, low] = value.to_be_bytes();
+
+        match address {
+            UART_DATA => {
+                if self.tx_fifo.len() < FIFO_SIZE {
+                    self.tx_fifo.push_back(low);
+                } else {
+                    self.status |= STATUS_TX_OVERRUN;
+                }
+            }
+            // Writing the status clears the overrun flag
+            UART_STATUS => self.status = 0,
+            UART_CONTROL => self.control = low,
+            _ => return Err(VmError::OutOfBounds { address }),
+        }
+
+        Ok(())
+    }
+}
+
+impl Device for UartDevice {
+    fn get_u16(&mut self, address: usize) -> Result<u16, VmError> {
+        self.get_register_u16(address)
+    }
+
+    fn get_u8(&mut self, address: usize) -> Result<u8, VmError> {
+        // The high bytes read as 0 without popping a received byte
+        if address & 1 == 0 && address <= UART_CONTROL {
+            return Ok(0);
+        }
+
+        self.get_register_u8(address)
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.set_register_u16(address, value)
+    }
+
+    fn set_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        if address & 1 == 0 && address <= UART_CONTROL {
+            return Ok(());
+        }
+
+        // The high byte is always 0, so the register is not read back first
+        self.set_register(address & !1, value.into())
+    }
+
+    fn reset(&mut self) {
+        self.rx_fifo.clear();
+        self.tx_fifo.clear();
+        self.status = 0;
+        self.control = 0;
+    }
+
+    fn tick(&mut self, _cycles: u64) {
+        // The guest has no way to learn about host errors, the bytes are dropped
+        if !self.tx_fifo.is_empty() && self.flush().is_err() {
+            self.tx_fifo.clear();
+        }
+        self.poll_input();
+    }
+
+    fn irq_pending(&self) -> Option<u16> {
+        if self.control & CONTROL_RX_INTERRUPT_ENABLE != 0 && !self.rx_fifo.is_empty() {
+            self.interrupt_vector
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for UartDevice {
+    fn drop(&mut self) {
+        let _ = self.flush();
+
+        // Shutting the socket down also ends the blocked read of the input thread
+        #[cfg(unix)]
+        if let Some(socket) = &self.socket {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::{
+        cpu::CPU, instructions, keyboard_device::ScriptedInput, memory::Memory,
+        memory_mapper::MemoryMapper,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[cfg(unix)]
+    use std::{io::Read, time::Duration};
+
+    /// Output that can still be inspected once given to the device.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, bytes: &[u8]) -> Result<usize, io::Error> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn echo_test() {
+        let output = SharedOutput::default();
+        let input = ScriptedInput::new(b"ok\0");
+        let uart = UartDevice::new(Some(Box::new(input)), Box::new(output.clone()));
+
+        let mut memory_mapper = MemoryMapper::new();
+        memory_mapper
+            .map_memory(Memory::new(0x10000), 0x0000, 0xFFFF, false)
+            .unwrap();
+        memory_mapper
+            .map(Box::new(uart), 0x4000, 0x4002, true)
+            .unwrap();
+
+        #[rustfmt::skip]
+        let program = [
+            instructions::MOV_MEM_REG, 0x40, 0x00, 1,         // mov &4000, acc
+            instructions::MOV_REG_MEM, 1, 0x40, 0x00,         // mov acc, &4000
+            instructions::JMP_NOT_EQ, 0x00, 0x00, 0x00, 0x00, // jne $0, &0000
+            instructions::HLT,
+        ];
+        memory_mapper
+            .load_image(&program, 0x0000, "program")
+            .unwrap();
+
+        let mut cpu = CPU::new(memory_mapper).unwrap();
+        // Gives the UART a cycle to receive the input
+        cpu.step().unwrap();
+        cpu.set_register("ip", 0).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(*output.0.borrow(), b"ok\0");
+    }
+
+    #[test]
+    fn fifo_test() {
+        let output = SharedOutput::default();
+        let mut uart = UartDevice::new(None, Box::new(output.clone()));
+
+        assert_eq!(uart.get_u16(UART_STATUS), Ok(STATUS_TX_READY.into()));
+        for byte in 0..=FIFO_SIZE as u8 {
+            uart.set_u16(UART_DATA, byte.into()).unwrap();
+        }
+        assert_eq!(uart.get_u16(UART_STATUS), Ok(STATUS_TX_OVERRUN.into()));
+
+        uart.tick(1);
+        assert_eq!(output.0.borrow().len(), FIFO_SIZE);
+        assert_eq!(
+            uart.get_u16(UART_STATUS),
+            Ok((STATUS_TX_READY | STATUS_TX_OVERRUN).into())
+        );
+
+        uart.set_u16(UART_STATUS, 0).unwrap();
+        assert_eq!(uart.get_u16(UART_STATUS), Ok(STATUS_TX_READY.into()));
+        assert_eq!(
+            uart.get_u16(UART_STATUS + 1),
+            Err(VmError::MisalignedRegister {
+                address: UART_STATUS + 1
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_test() {
+        let (guest, mut host) = UnixStream::pair().unwrap();
+        let mut uart = UartDevice::from_unix_stream(guest).unwrap();
+
+        uart.set_u16(UART_DATA, b'!'.into()).unwrap();
+        uart.tick(1);
+
+        let mut byte = [0];
+        host.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"!");
+
+        host.write_all(b"?").unwrap();
+        while uart.get_u16(UART_STATUS).unwrap() & u16::from(STATUS_RX_AVAILABLE) == 0 {
+            uart.tick(1);
+        }
+        assert_eq!(uart.get_u16(UART_DATA), Ok(b'?'.into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_drop_test() {
+        let (guest, mut host) = UnixStream::pair().unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut uart = UartDevice::from_unix_stream(guest).unwrap();
+
+        uart.set_u16(UART_DATA, b'!'.into()).unwrap();
+        drop(uart);
+
+        let mut bytes = Vec::new();
+        host.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, b"!");
+    }
+}